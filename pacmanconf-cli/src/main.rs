@@ -0,0 +1,643 @@
+use std::env;
+use std::error;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+
+use pacmanconf::{Config, Error, ErrorKind, Lock, Options, Session};
+
+const PACMAN_CONF: &str = "/etc/pacman.conf";
+
+const USAGE: &str = "\
+Usage: pacmanconf [options] [<directive>...]
+       pacmanconf [options] --repo-list
+       pacmanconf [options] --repo=<remote> [<directive>...]
+
+Query pacman's configuration file. Compatible with pacman-conf.
+
+Options:
+  -c, --config=<path>         set an alternate configuration file
+  -R, --rootdir=<path>        set an alternate installation root
+  -r, --repo=<remote>         query options for a specific repo
+  -l, --repo-list             list configured repositories
+  -v, --verbose               always show directive names
+  -h, --help                  display this help information
+  -V, --version               display version information
+
+Extras:
+      --native                read the config without using pacman-conf
+      --json                  print the config, repo or repo list as JSON
+      --shell                 print the config as shell variables for eval
+      --canonical             normalise the config before printing or diffing it
+      --fingerprint           print a hash of the canonical config
+      --diff=<path>           show how another config differs, exits 1 if it does
+      --freeze                print the config as a standalone lock file
+      --verify=<path>         show how the config drifted from a lock, exits 1 if it did
+      --validate              check the config and its Includes for errors
+      --set=<key>[=<value>]   set an option in the file that defines it
+      --unset=<key>           comment out an option
+      --enable-repo=<name>    uncomment a repository
+      --disable-repo=<name>   comment out a repository
+
+The config is read using pacman-conf if it is installed and directly otherwise.
+";
+
+type Result<T> = std::result::Result<T, Box<dyn error::Error>>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Edit {
+    Set(String, Option<String>),
+    Unset(String),
+    EnableRepo(String),
+    DisableRepo(String),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Args {
+    config: Option<String>,
+    root: Option<String>,
+    repo: Option<String>,
+    repo_list: bool,
+    verbose: bool,
+    help: bool,
+    version: bool,
+    native: bool,
+    json: bool,
+    shell: bool,
+    canonical: bool,
+    fingerprint: bool,
+    diff: Option<String>,
+    freeze: bool,
+    verify: Option<String>,
+    validate: bool,
+    edits: Vec<Edit>,
+    directives: Vec<String>,
+}
+
+impl Args {
+    fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Args> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                parsed.directives.extend(args.by_ref());
+            } else if let Some(long) = arg.strip_prefix("--") {
+                let (name, value) = match long.split_once('=') {
+                    Some((name, value)) => (name, Some(value.to_string())),
+                    None => (long, None),
+                };
+                parsed.long(name, value, &mut args)?;
+            } else if arg.len() > 1 && arg.starts_with('-') {
+                let shorts = &arg[1..];
+                for (i, c) in shorts.char_indices() {
+                    if parsed.short_flag(c) {
+                        continue;
+                    }
+                    let rest = &shorts[i + c.len_utf8()..];
+                    let value = if rest.is_empty() {
+                        None
+                    } else {
+                        Some(rest.to_string())
+                    };
+                    parsed.short(c, value, &mut args)?;
+                    break;
+                }
+            } else {
+                parsed.directives.push(arg);
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    fn short_flag(&mut self, c: char) -> bool {
+        match c {
+            'l' => self.repo_list = true,
+            'v' => self.verbose = true,
+            'h' => self.help = true,
+            'V' => self.version = true,
+            _ => return false,
+        }
+        true
+    }
+
+    fn short<I>(&mut self, c: char, value: Option<String>, args: &mut I) -> Result<()>
+    where
+        I: Iterator<Item = String>,
+    {
+        let name = match c {
+            'c' => "config",
+            'R' => "rootdir",
+            'r' => "repo",
+            _ => return Err(format!("invalid option '-{}'", c).into()),
+        };
+        self.long(name, value, args)
+    }
+
+    fn long<I>(&mut self, name: &str, value: Option<String>, args: &mut I) -> Result<()>
+    where
+        I: Iterator<Item = String>,
+    {
+        let flag = match name {
+            "repo-list" => Some(&mut self.repo_list),
+            "verbose" => Some(&mut self.verbose),
+            "help" => Some(&mut self.help),
+            "version" => Some(&mut self.version),
+            "native" => Some(&mut self.native),
+            "json" => Some(&mut self.json),
+            "shell" => Some(&mut self.shell),
+            "canonical" => Some(&mut self.canonical),
+            "fingerprint" => Some(&mut self.fingerprint),
+            "freeze" => Some(&mut self.freeze),
+            "validate" => Some(&mut self.validate),
+            _ => None,
+        };
+        if let Some(flag) = flag {
+            if value.is_some() {
+                return Err(format!("option '--{}' doesn't allow an argument", name).into());
+            }
+            *flag = true;
+            return Ok(());
+        }
+
+        let value = || {
+            value
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("option '--{}' requires an argument", name))
+        };
+
+        match name {
+            "config" => self.config = Some(value()?),
+            // pacman-conf accepts abbreviated long options and the library passes --root
+            "root" | "rootdir" => self.root = Some(value()?),
+            "repo" => self.repo = Some(value()?),
+            "diff" => self.diff = Some(value()?),
+            "verify" => self.verify = Some(value()?),
+            "set" => {
+                let value = value()?;
+                let edit = match value.split_once('=') {
+                    Some((key, value)) => Edit::Set(key.trim().into(), Some(value.trim().into())),
+                    None => Edit::Set(value, None),
+                };
+                self.edits.push(edit);
+            }
+            "unset" => self.edits.push(Edit::Unset(value()?)),
+            "enable-repo" => self.edits.push(Edit::EnableRepo(value()?)),
+            "disable-repo" => self.edits.push(Edit::DisableRepo(value()?)),
+            _ => return Err(format!("unrecognized option '--{}'", name).into()),
+        }
+
+        Ok(())
+    }
+
+    fn options(&self, config: Option<&str>) -> Options {
+        let mut options = Config::options();
+        if let Some(config) = config {
+            options.pacman_conf(config);
+        }
+        if let Some(root) = &self.root {
+            options.root_dir(root.as_str());
+        }
+        options
+    }
+
+    /// Reads a config using pacman-conf, falling back to reading it directly if pacman-conf
+    /// is not installed.
+    fn read(&self, config: Option<&str>) -> Result<Config> {
+        let options = self.options(config);
+        if self.native {
+            return Ok(options.read_native()?);
+        }
+
+        match options.read() {
+            Err(Error {
+                kind: ErrorKind::BinaryNotFound(_),
+                ..
+            }) => Ok(options.read_native()?),
+            res => Ok(res?),
+        }
+    }
+}
+
+/// The directives of `[options]` that pacman-conf can be queried for.
+const OPTION_DIRECTIVES: &[&str] = &[
+    "RootDir",
+    "DBPath",
+    "CacheDir",
+    "HookDir",
+    "GPGDir",
+    "LogFile",
+    "HoldPkg",
+    "IgnorePkg",
+    "IgnoreGroup",
+    "NoUpgrade",
+    "NoExtract",
+    "Architecture",
+    "XferCommand",
+    "UseSyslog",
+    "Color",
+    "TotalDownload",
+    "CheckSpace",
+    "VerbosePkgLists",
+    "DisableDownloadTimeout",
+    "ILoveCandy",
+    "DisableSandbox",
+    "DisableSandboxFilesystem",
+    "DisableSandboxSyscalls",
+    "UseDelta",
+    "ParallelDownloads",
+    "CleanMethod",
+    "SigLevel",
+    "LocalFileSigLevel",
+    "RemoteFileSigLevel",
+    "DownloadUser",
+];
+
+/// The directives of a repository that pacman-conf can be queried for.
+const REPO_DIRECTIVES: &[&str] = &["Usage", "SigLevel", "Server"];
+
+/// Prints directives in the format pacman-conf uses. If keys is empty every directive is
+/// printed, otherwise the values of each key in turn.
+///
+/// As with pacman-conf keys are not case sensitive, and asking for a key that is not a
+/// directive is an error once the rest have been printed.
+fn print_directives(
+    out: &mut String,
+    directives: &[(&str, Option<String>)],
+    known: &[&str],
+    keys: &[String],
+    verbose: bool,
+) -> Result<()> {
+    let print = |out: &mut String, key: &str, value: &Option<String>| match value {
+        Some(value) if verbose => writeln!(out, "{} = {}", key, value),
+        Some(value) => writeln!(out, "{}", value),
+        None => writeln!(out, "{}", key),
+    };
+
+    if keys.is_empty() {
+        for (key, value) in directives {
+            print(out, key, value)?;
+        }
+        return Ok(());
+    }
+
+    let mut unknown = Vec::new();
+    for key in keys {
+        match known.iter().find(|k| k.eq_ignore_ascii_case(key)) {
+            Some(known) => {
+                for (key, value) in directives.iter().filter(|(k, _)| k == known) {
+                    print(out, key, value)?;
+                }
+            }
+            None => unknown.push(key.as_str()),
+        }
+    }
+
+    match unknown.as_slice() {
+        [] => Ok(()),
+        [key] if key.eq_ignore_ascii_case("Include") => {
+            Err("'Include' directives cannot be queried".into())
+        }
+        _ => Err(format!("unknown directive '{}'", unknown.join("', '")).into()),
+    }
+}
+
+fn edit(out: &mut String, args: &Args) -> Result<i32> {
+    let path = args.config.as_deref().unwrap_or(PACMAN_CONF);
+    let mut session = Session::open(path)?;
+
+    for edit in &args.edits {
+        match edit {
+            Edit::Set(key, value) => session.set_option(key, value.as_deref())?,
+            Edit::Unset(key) => session.unset_option(key)?,
+            Edit::EnableRepo(name) => session.enable_repo(name)?,
+            Edit::DisableRepo(name) => session.disable_repo(name)?,
+        };
+    }
+
+    for file in session.save()? {
+        writeln!(out, "modified {}", file)?;
+    }
+    Ok(0)
+}
+
+/// Freezes the config into a lock file, or shows how it drifted from one.
+fn lock(out: &mut String, args: &Args) -> Result<i32> {
+    let path = args.config.as_deref().unwrap_or(PACMAN_CONF);
+    let files = args.options(Some(path)).provenance()?.files().to_vec();
+    let config = args.read(args.config.as_deref())?;
+
+    let lock = match &args.verify {
+        Some(lock) => lock,
+        None => {
+            write!(out, "{}", Lock::new(&config, &files))?;
+            return Ok(0);
+        }
+    };
+
+    let lock = fs::read_to_string(lock)
+        .map_err(|err| format!("could not read {}: {}", lock, err))?
+        .parse::<Lock>()?;
+    let drift = lock.drift(&config, &files);
+    for file in &drift.changed_files {
+        writeln!(out, "changed {}", file)?;
+    }
+    for file in &drift.added_files {
+        writeln!(out, "added {}", file)?;
+    }
+    for file in &drift.removed_files {
+        writeln!(out, "removed {}", file)?;
+    }
+    write!(out, "{}", drift.diff)?;
+    Ok(if drift.is_empty() { 0 } else { 1 })
+}
+
+fn run(out: &mut String, args: Args) -> Result<i32> {
+    if args.help {
+        write!(out, "{}", USAGE)?;
+        return Ok(0);
+    }
+    if args.version {
+        writeln!(out, "pacmanconf v{}", env!("CARGO_PKG_VERSION"))?;
+        return Ok(0);
+    }
+    if !args.edits.is_empty() {
+        return edit(out, &args);
+    }
+    if args.validate {
+        let config = args.config.as_deref().unwrap_or(PACMAN_CONF);
+        args.options(Some(config)).read_native()?;
+        return Ok(0);
+    }
+    if args.json && !args.directives.is_empty() {
+        return Err("--json can not be used when querying directives".into());
+    }
+    if args.shell && (args.json || args.repo.is_some() || !args.directives.is_empty()) {
+        return Err("--shell prints the whole config and can not be combined".into());
+    }
+    if args.fingerprint
+        && (args.json
+            || args.shell
+            || args.diff.is_some()
+            || args.repo_list
+            || args.repo.is_some()
+            || !args.directives.is_empty())
+    {
+        return Err("--fingerprint hashes the whole config and can not be combined".into());
+    }
+    if args.freeze || args.verify.is_some() {
+        if args.freeze && args.verify.is_some()
+            || args.json
+            || args.shell
+            || args.canonical
+            || args.fingerprint
+            || args.diff.is_some()
+            || args.repo_list
+            || args.repo.is_some()
+            || !args.directives.is_empty()
+        {
+            return Err("--freeze and --verify can not be combined".into());
+        }
+        return lock(out, &args);
+    }
+
+    let read = |path: Option<&str>| -> Result<Config> {
+        let config = args.read(path)?;
+        Ok(if args.canonical {
+            config.canonical()
+        } else {
+            config
+        })
+    };
+    let config = read(args.config.as_deref())?;
+
+    if args.fingerprint {
+        writeln!(out, "{}", config.fingerprint())?;
+        return Ok(0);
+    }
+
+    if let Some(other) = &args.diff {
+        let diff = config.diff(&read(Some(other))?);
+        if args.json {
+            writeln!(out, "{}", serde_json::to_string_pretty(&diff)?)?;
+        } else {
+            write!(out, "{}", diff)?;
+        }
+        return Ok(if diff.is_empty() { 0 } else { 1 });
+    }
+
+    if args.repo_list {
+        let names = config.repos.iter().map(|r| &r.name).collect::<Vec<_>>();
+        if args.json {
+            writeln!(out, "{}", serde_json::to_string_pretty(&names)?)?;
+        } else {
+            for name in names {
+                writeln!(out, "{}", name)?;
+            }
+        }
+    } else if let Some(name) = &args.repo {
+        let repo = config
+            .repos
+            .iter()
+            .find(|r| r.name == *name)
+            .ok_or_else(|| format!("repo '{}' not configured", name))?;
+
+        if args.json {
+            writeln!(out, "{}", serde_json::to_string_pretty(repo)?)?;
+        } else {
+            let verbose = args.verbose || args.directives.is_empty();
+            let directives = repo.directives();
+            print_directives(out, &directives, REPO_DIRECTIVES, &args.directives, verbose)?;
+        }
+    } else if args.json {
+        writeln!(out, "{}", serde_json::to_string_pretty(&config)?)?;
+    } else if args.shell {
+        write!(out, "{}", config.shell()?)?;
+    } else if args.directives.is_empty() {
+        write!(out, "{}", config)?;
+    } else {
+        let directives = config.directives();
+        print_directives(
+            out,
+            &directives,
+            OPTION_DIRECTIVES,
+            &args.directives,
+            args.verbose,
+        )?;
+    }
+
+    Ok(0)
+}
+
+fn main() {
+    let mut out = String::new();
+    let code = Args::parse(env::args().skip(1))
+        .and_then(|args| run(&mut out, args))
+        .unwrap_or_else(|err| {
+            eprintln!("error: {}", err);
+            1
+        });
+
+    // output is buffered so a closed pipe, as with `| head`, is not an error
+    match io::stdout().write_all(out.as_bytes()) {
+        Err(err) if err.kind() != io::ErrorKind::BrokenPipe => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+        _ => process::exit(code),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Args {
+        Args::parse(args.split_whitespace().map(Into::into)).unwrap()
+    }
+
+    #[test]
+    fn parse_args() {
+        assert_eq!(
+            parse("-c tests/pacman.conf --root=/chroot -lv DBPath"),
+            Args {
+                config: Some("tests/pacman.conf".into()),
+                root: Some("/chroot".into()),
+                repo_list: true,
+                verbose: true,
+                directives: vec!["DBPath".into()],
+                ..Default::default()
+            }
+        );
+        assert_eq!(parse("-rcore Server").repo.as_deref(), Some("core"));
+        assert_eq!(parse("--rootdir /mnt").root.as_deref(), Some("/mnt"));
+        assert_eq!(parse("-- -v").directives, ["-v"]);
+        assert_eq!(
+            parse("--set ParallelDownloads=5 --set=Color --disable-repo core").edits,
+            [
+                Edit::Set("ParallelDownloads".into(), Some("5".into())),
+                Edit::Set("Color".into(), None),
+                Edit::DisableRepo("core".into()),
+            ]
+        );
+
+        let parse = |args: &[&str]| Args::parse(args.iter().map(|s| s.to_string()));
+        assert!(parse(&["--config"]).is_err());
+        assert!(parse(&["--json=yes"]).is_err());
+        assert!(parse(&["--nope"]).is_err());
+        assert!(parse(&["-x"]).is_err());
+    }
+
+    #[test]
+    fn read_native() {
+        let path = env::temp_dir().join(format!("pacmanconf-cli-{}.conf", process::id()));
+        std::fs::write(
+            &path,
+            "[options]\nDownloadUser = alpm\n[core]\nServer = https://a.example/$repo\n",
+        )
+        .unwrap();
+
+        let args = parse("--native --root /chroot");
+        let config = args.read(Some(&path.to_string_lossy())).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.root_dir, "/chroot");
+        assert_eq!(config.download_user.as_deref(), Some("alpm"));
+        assert_eq!(config.repos[0].servers, ["https://a.example/core"]);
+    }
+
+    #[test]
+    fn query() {
+        let path = env::temp_dir().join(format!("pacmanconf-cli-query-{}.conf", process::id()));
+        let path = path.to_string_lossy().into_owned();
+        std::fs::write(
+            &path,
+            "[options]\nDBPath = /db/\nColor\n[core]\nServer = https://a.example\nSigLevel = Never\n",
+        )
+        .unwrap();
+        let query = |args: &str| {
+            let mut out = String::new();
+            let res = run(&mut out, parse(&format!("--native -c {} {}", path, args)));
+            (res.map_err(|e| e.to_string()), out)
+        };
+
+        assert_eq!(query("color dbpath"), (Ok(0), "Color\n/db/\n".into()));
+        assert_eq!(query("-v DBPath"), (Ok(0), "DBPath = /db/\n".into()));
+        assert_eq!(query("XferCommand"), (Ok(0), "".into()));
+        assert_eq!(
+            query("DBPath Nope"),
+            (Err("unknown directive 'Nope'".into()), "/db/\n".into())
+        );
+        assert!(query("Include").0.is_err());
+        assert_eq!(
+            query("--repo=core"),
+            (
+                Ok(0),
+                "Usage = All\nSigLevel = PackageNever\nSigLevel = DatabaseNever\n\
+                 Server = https://a.example\n"
+                    .into()
+            )
+        );
+        assert_eq!(
+            query("--repo=core server"),
+            (Ok(0), "https://a.example\n".into())
+        );
+        assert!(query("--repo=core DBPath").0.is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn fingerprint() {
+        let path = env::temp_dir().join(format!("pacmanconf-cli-fp-{}.conf", process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let fingerprint = |contents: &str| {
+            std::fs::write(&path, contents).unwrap();
+            let mut out = String::new();
+            run(
+                &mut out,
+                parse(&format!("--native --fingerprint -c {}", path)),
+            )
+            .unwrap();
+            out
+        };
+
+        let a = fingerprint("[options]\nColor\nIgnorePkg = b a\n[core]\n");
+        let b = fingerprint("# comment\n[options]\nIgnorePkg = a\nIgnorePkg = b\n[core]\n");
+        let c = fingerprint("[options]\nIgnorePkg = a\n[core]\n");
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(a.trim().len(), 64);
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert!(run(&mut String::new(), parse("--fingerprint --json")).is_err());
+    }
+
+    #[test]
+    fn freeze() {
+        let dir = env::temp_dir().join(format!("pacmanconf-cli-lock-{}", process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let conf = dir.join("pacman.conf").to_string_lossy().into_owned();
+        let lock = dir.join("lock.conf").to_string_lossy().into_owned();
+        std::fs::write(&conf, "[core]\nServer = https://a.example/$repo\n").unwrap();
+
+        let mut out = String::new();
+        run(&mut out, parse(&format!("--native --freeze -c {}", conf))).unwrap();
+        assert!(out.contains("Server = https://a.example/core\n"));
+        std::fs::write(&lock, &out).unwrap();
+
+        let verify = format!("--native -c {} --verify {}", conf, lock);
+        let mut out = String::new();
+        assert_eq!(run(&mut out, parse(&verify)).unwrap(), 0);
+        assert_eq!(out, "");
+
+        std::fs::write(&conf, "[core]\nServer = https://b.example/$repo\n").unwrap();
+        let mut out = String::new();
+        assert_eq!(run(&mut out, parse(&verify)).unwrap(), 1);
+        assert!(out.starts_with(&format!("changed {}\n", conf)));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
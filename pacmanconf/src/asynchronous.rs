@@ -0,0 +1,94 @@
+use std::io;
+
+use crate::{process, Config, Error, Options};
+
+impl Options {
+    /// Read the config file into a config instance without blocking.
+    ///
+    /// This is [`read`](Options::read) for use with tokio. pacman-conf is killed if it
+    /// runs past the [`timeout`](Options::timeout) or if the future is dropped.
+    pub async fn read_async(&self) -> Result<Config, Error> {
+        self.finish(self.expand_async().await?.parse()?, None)
+    }
+
+    /// Expand and dump the config file into a string without blocking.
+    ///
+    /// See [`read_async`](Options::read_async).
+    pub async fn expand_async(&self) -> Result<String, Error> {
+        let stdout = process::run_async(self.command(), self.get_timeout()).await?;
+        process::expanded(stdout)
+    }
+
+    /// Read config file contents held in memory into a config instance without blocking.
+    ///
+    /// See [`expand_str`](Options::expand_str) and [`read_async`](Options::read_async).
+    pub async fn read_str_async(&self, contents: &str) -> Result<Config, Error> {
+        self.finish(
+            self.expand_str_async(contents).await?.parse()?,
+            Some(contents),
+        )
+    }
+
+    /// Expand and dump config file contents held in memory into a string without
+    /// blocking.
+    ///
+    /// See [`expand_str`](Options::expand_str) and [`read_async`](Options::read_async).
+    pub async fn expand_str_async(&self, contents: &str) -> Result<String, Error> {
+        let dir = self.temp_dir();
+        let contents = contents.as_bytes().to_vec();
+        let file = tokio::task::spawn_blocking(move || process::TempFile::new(&dir, &contents))
+            .await
+            .map_err(io::Error::from)??;
+
+        let stdout = process::run_async(self.temp_command(&file), self.get_timeout()).await?;
+        process::expanded(stdout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorKind;
+    use std::time::{Duration, Instant};
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn read_async() {
+        let bin = process::fake_pacman_conf("async", "[ -n \"$SLOW\" ] && sleep 5\ncat \"$2\"");
+
+        let mut options = Config::options();
+        options.pacman_conf_bin(bin.to_str().unwrap());
+
+        let config = options.read_str_async("[options]\nColor\n").await.unwrap();
+        assert!(config.color);
+
+        let config = options
+            .clone()
+            .pacman_conf("tests/pacman.conf")
+            .read_async()
+            .await
+            .unwrap();
+        assert_eq!(config.repos.len(), 7);
+
+        let start = Instant::now();
+        let err = options
+            .clone()
+            .env("SLOW", "1")
+            .timeout(Duration::from_millis(100))
+            .expand_str_async("[options]\n")
+            .await
+            .unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Timeout(_)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let err = options
+            .clone()
+            .pacman_conf_bin("/nonexistent/pacman-conf")
+            .expand_async()
+            .await
+            .unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::BinaryNotFound(_)));
+
+        std::fs::remove_file(bin).unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+use std::fs;
+use std::path::Path;
+
+use crate::document::{classify, LineKind};
+use crate::error::Error;
+
+/// The kind of line that has been commented out.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum CommentedKind {
+    /// A repository section has been commented out.
+    /// This variant contains the repository name.
+    Repo(String),
+    /// A directive has been commented out. This variant contains:
+    ///
+    /// - The section the directive would belong to (if any)
+    /// - The key of the directive
+    /// - The value of the directive (if any)
+    Directive(Option<String>, String, Option<String>),
+}
+
+/// A commented out line that would be valid pacman.conf syntax if it were uncommented.
+///
+/// Stock pacman.conf files ship many options and repositories disabled, such as
+/// `#Color` or a `#[multilib]` section. These are dropped by the normal parser so
+/// this allows finding them so that they may be enabled.
+///
+/// ```
+/// use pacmanconf::{Commented, CommentedKind};
+///
+/// let conf = "
+/// [options]
+/// #Color
+///
+/// #[multilib]
+/// #Include = /etc/pacman.d/mirrorlist
+/// ";
+///
+/// let commented = Commented::scan_str(conf);
+/// assert_eq!(commented[0].kind, CommentedKind::Directive(Some("options".into()), "Color".into(), None));
+/// assert_eq!(commented[1].kind, CommentedKind::Repo("multilib".into()));
+/// assert_eq!(commented[2].line_number, 6);
+/// ```
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Commented {
+    /// The file the line was found in (if any)
+    pub filename: Option<String>,
+    /// The line number of the line
+    pub line_number: usize,
+    /// The full line, including the comment character
+    pub line: String,
+    /// What the line would be if it were uncommented
+    pub kind: CommentedKind,
+}
+
+impl Commented {
+    /// Finds all the commented out directives and repositories in a str.
+    pub fn scan_str(ini: &str) -> Vec<Commented> {
+        Self::scan(None, ini)
+    }
+
+    /// Finds all the commented out directives and repositories in a str. Optionally a filename
+    /// can be supplied which will be recorded in the results.
+    ///
+    /// Only lines that would be accepted by pacman if uncommented are returned. Comments such as
+    /// `# Misc options` are ignored. Includes are not followed.
+    pub fn scan(filename: Option<&str>, ini: &str) -> Vec<Commented> {
+        classify(ini)
+            .into_iter()
+            .zip(ini.lines())
+            .enumerate()
+            .filter(|(_, (line, _))| line.commented)
+            .filter_map(|(i, (line, raw))| {
+                let kind = match line.kind? {
+                    LineKind::Section(section) => CommentedKind::Repo(section),
+                    LineKind::Directive(key, value) => {
+                        CommentedKind::Directive(line.section, key, value)
+                    }
+                };
+
+                Some(Commented {
+                    filename: filename.map(Into::into),
+                    line_number: i + 1,
+                    line: raw.into(),
+                    kind,
+                })
+            })
+            .collect()
+    }
+
+    /// Finds all the commented out directives and repositories in a file.
+    pub fn scan_file<P: AsRef<Path>>(path: P) -> Result<Vec<Commented>, Error> {
+        let path = path.as_ref();
+        let ini = fs::read_to_string(path)?;
+        Ok(Self::scan(Some(&path.to_string_lossy()), &ini))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn directive(section: &str, key: &str, value: Option<&str>) -> CommentedKind {
+        CommentedKind::Directive(Some(section.into()), key.into(), value.map(Into::into))
+    }
+
+    #[test]
+    fn scan_pacman_conf() {
+        let commented = Commented::scan_file("tests/pacman.conf").unwrap();
+        let kinds = commented.iter().map(|c| c.kind.clone()).collect::<Vec<_>>();
+
+        assert_eq!(
+            kinds,
+            vec![
+                directive(
+                    "options",
+                    "XferCommand",
+                    Some("/usr/bin/curl -C - -f %u > %o")
+                ),
+                directive(
+                    "options",
+                    "XferCommand",
+                    Some("/usr/bin/wget --passive-ftp -c -O %o %u")
+                ),
+                directive("options", "CleanMethod", Some("KeepInstalled")),
+                directive("options", "IgnoreGroup", Some("")),
+                directive("options", "NoUpgrade", Some("")),
+                directive("options", "NoExtract", Some("")),
+                directive("options", "UseSyslog", None),
+                directive("options", "DisableSandboxFilesystem", None),
+                directive("options", "DisableSandboxSyscalls", None),
+            ]
+        );
+
+        assert_eq!(commented[0].line_number, 19);
+        assert_eq!(
+            commented[0].line,
+            "#XferCommand = /usr/bin/curl -C - -f %u > %o"
+        );
+        assert_eq!(commented[0].filename.as_deref(), Some("tests/pacman.conf"));
+    }
+
+    #[test]
+    fn scan_repos() {
+        let conf = "
+[options]
+#Color = yes
+#ParallelDownloads
+
+[core]
+#Server = https://example.com/$repo/os/$arch
+Include = /etc/pacman.d/mirrorlist
+
+# An example of a disabled repository
+#[multilib]
+#Include = /etc/pacman.d/mirrorlist
+
+#[custom]
+#SigLevel = Optional TrustAll
+#Server = file:///home/custompkgs
+#[not a repo]
+";
+
+        let commented = Commented::scan_str(conf);
+        let kinds = commented.iter().map(|c| c.kind.clone()).collect::<Vec<_>>();
+
+        assert_eq!(
+            kinds,
+            vec![
+                directive("core", "Server", Some("https://example.com/$repo/os/$arch")),
+                CommentedKind::Repo("multilib".into()),
+                directive("multilib", "Include", Some("/etc/pacman.d/mirrorlist")),
+                CommentedKind::Repo("custom".into()),
+                directive("custom", "SigLevel", Some("Optional TrustAll")),
+                directive("custom", "Server", Some("file:///home/custompkgs")),
+            ]
+        );
+        assert_eq!(commented[1].line_number, 11);
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::document::validate_option;
+use crate::error::{Error, ErrorKind};
+use crate::keys;
+use crate::native::{self, SigLevel};
+
+/// The value of an option in a [`Declaration`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    /// A flag such as `Color`. The option is left out when false.
+    Flag(bool),
+    /// A number such as `ParallelDownloads`.
+    Number(u64),
+    /// A single value. Options that take a list may also be given as a whitespace
+    /// separated string.
+    String(String),
+    /// A list of values for options such as `IgnorePkg`.
+    List(Vec<String>),
+}
+
+/// A repository in a [`Declaration`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RepoDeclaration {
+    /// The name of the repository
+    pub name: String,
+    /// Files to Include, such as a mirrorlist. These may be generated mirrorlists from
+    /// [`Declaration::mirrorlists`] or existing files.
+    pub include: Vec<String>,
+    /// Servers given inline
+    pub servers: Vec<String>,
+    /// CacheServers given inline
+    pub cache_servers: Vec<String>,
+    /// SigLevel
+    pub sig_level: Vec<String>,
+    /// Usage
+    pub usage: Vec<String>,
+}
+
+/// A pacman.conf and its mirrorlists rendered from a [`Declaration`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Rendered {
+    /// The contents of pacman.conf
+    pub pacman_conf: String,
+    /// The path and contents of each generated mirrorlist
+    pub mirrorlists: Vec<(String, String)>,
+}
+
+/// A declarative description of a pacman.conf.
+///
+/// A declaration can be loaded from any format serde supports, such as TOML or YAML.
+/// Options are given under their pacman.conf key names. Repositories are given in the
+/// order pacman should use them and can list their servers inline, Include existing
+/// files, or Include mirrorlists that are generated from the declaration.
+///
+/// ```toml
+/// [options]
+/// HoldPkg = ["pacman", "glibc"]
+/// Architecture = "auto"
+/// Color = true
+/// ParallelDownloads = 5
+/// SigLevel = "Required DatabaseOptional"
+///
+/// [mirrorlists]
+/// "/etc/pacman.d/mirrorlist" = ["https://geo.mirror.pkgbuild.com/$repo/os/$arch"]
+///
+/// [[repos]]
+/// name = "core"
+/// include = ["/etc/pacman.d/mirrorlist"]
+///
+/// [[repos]]
+/// name = "custom"
+/// servers = ["file:///home/custompkgs"]
+/// sig_level = ["Optional", "TrustAll"]
+/// ```
+///
+/// [`render`](Declaration::render) checks the declaration against pacman's rules and
+/// produces pacman.conf and the mirrorlists.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Declaration {
+    /// The directives of the `[options]` section
+    pub options: BTreeMap<String, Value>,
+    /// The repositories, in order
+    pub repos: Vec<RepoDeclaration>,
+    /// Mirrorlists to generate, by path, holding the servers of each
+    pub mirrorlists: BTreeMap<String, Vec<String>>,
+}
+
+fn invalid(section: &str, key: &str, value: &str) -> Error {
+    ErrorKind::InvalidValue(section.into(), key.into(), value.into()).into()
+}
+
+/// Checks that a value fits on a single line of pacman.conf.
+fn check_line(section: &str, key: &str, value: &str) -> Result<(), Error> {
+    if value.contains(['\n', '\r']) || value.trim().is_empty() {
+        return Err(invalid(section, key, value));
+    }
+    Ok(())
+}
+
+impl Value {
+    /// The value as it is written in pacman.conf. None for a flag that is set and
+    /// Some(None) for one that is not.
+    fn to_directive(&self, key: &str) -> Result<Option<Option<String>>, Error> {
+        let value = match self {
+            Value::Flag(true) => Some(None),
+            Value::Flag(false) => None,
+            Value::Number(n) => Some(Some(n.to_string())),
+            Value::String(s) => Some(Some(s.clone())),
+            Value::List(_) if !keys::is_list(key) => {
+                return Err(invalid("options", key, "[...]"));
+            }
+            Value::List(list) => {
+                for value in list {
+                    check_line("options", key, value)?;
+                    if value.contains(char::is_whitespace) {
+                        return Err(invalid("options", key, value));
+                    }
+                }
+                Some(Some(list.join(" ")))
+            }
+        };
+        Ok(value)
+    }
+}
+
+impl Declaration {
+    /// Checks the declaration against pacman's rules.
+    ///
+    /// Every option must be known to pacman and given the right kind of value, repository
+    /// names must be valid and unique, and SigLevel and Usage must only use values pacman
+    /// understands.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.render().map(|_| ())
+    }
+
+    /// Validates the declaration and renders pacman.conf and the generated mirrorlists.
+    pub fn render(&self) -> Result<Rendered, Error> {
+        let mut pacman_conf = String::from("[options]\n");
+
+        for (key, value) in &self.options {
+            if let Value::Flag(false) = value {
+                if keys::option_key(key) != Some(keys::Value::None) {
+                    return Err(invalid("options", key, "false"));
+                }
+            }
+
+            let value = match value.to_directive(key)? {
+                Some(value) => value,
+                None => continue,
+            };
+            validate_option(key, value.as_deref())?;
+
+            match value {
+                Some(value) => {
+                    check_line("options", key, &value)?;
+                    let values = value.split_whitespace().map(Into::into).collect::<Vec<_>>();
+                    match key.as_str() {
+                        "SigLevel" | "LocalFileSigLevel" | "RemoteFileSigLevel" => {
+                            SigLevel::parse("options", key, &values)?;
+                        }
+                        "CleanMethod" => {
+                            native::clean_method("options", &values)?;
+                        }
+                        _ => (),
+                    }
+                    writeln!(pacman_conf, "{} = {}", key, value).unwrap();
+                }
+                None => writeln!(pacman_conf, "{}", key).unwrap(),
+            }
+        }
+
+        for (i, repo) in self.repos.iter().enumerate() {
+            let name = &repo.name;
+            if !keys::is_repo_name(name) {
+                return Err(invalid("repos", "name", name));
+            }
+            if self.repos[..i].iter().any(|r| r.name == *name) {
+                return Err(invalid("repos", "name", name));
+            }
+
+            SigLevel::parse(name, "SigLevel", &repo.sig_level)?;
+            native::usage(name, &repo.usage)?;
+
+            writeln!(pacman_conf, "\n[{}]", name).unwrap();
+            let directives = [
+                ("SigLevel", &repo.sig_level, true),
+                ("Usage", &repo.usage, true),
+                ("Include", &repo.include, false),
+                ("Server", &repo.servers, false),
+                ("CacheServer", &repo.cache_servers, false),
+            ];
+            for (key, values, list) in directives {
+                for value in values {
+                    check_line(name, key, value)?;
+                }
+                match list {
+                    true if !values.is_empty() => {
+                        writeln!(pacman_conf, "{} = {}", key, values.join(" ")).unwrap()
+                    }
+                    true => (),
+                    false => values
+                        .iter()
+                        .for_each(|v| writeln!(pacman_conf, "{} = {}", key, v).unwrap()),
+                }
+            }
+        }
+
+        let mut mirrorlists = Vec::new();
+        for (path, servers) in &self.mirrorlists {
+            check_line("mirrorlists", "path", path)?;
+            let mut mirrorlist = String::new();
+            for server in servers {
+                check_line("mirrorlists", "Server", server)?;
+                writeln!(mirrorlist, "Server = {}", server).unwrap();
+            }
+            mirrorlists.push((path.clone(), mirrorlist));
+        }
+
+        Ok(Rendered {
+            pacman_conf,
+            mirrorlists,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    const DECLARATION: &str = r#"
+[options]
+HoldPkg = ["pacman", "glibc"]
+IgnorePkg = "linux vim-youcompleteme*"
+Architecture = "auto"
+Color = true
+CheckSpace = false
+ParallelDownloads = 5
+SigLevel = "Required DatabaseOptional"
+
+[mirrorlists]
+"/etc/pacman.d/mirrorlist" = [
+    "https://a.example/$repo/os/$arch",
+    "https://b.example/$repo/os/$arch",
+]
+
+[[repos]]
+name = "core"
+include = ["/etc/pacman.d/mirrorlist"]
+
+[[repos]]
+name = "custom"
+servers = ["file:///home/custompkgs"]
+sig_level = ["Optional", "TrustAll"]
+"#;
+
+    #[test]
+    fn render() {
+        let declaration: Declaration = toml::from_str(DECLARATION).unwrap();
+        let rendered = declaration.render().unwrap();
+
+        assert_eq!(
+            rendered.pacman_conf,
+            "\
+[options]
+Architecture = auto
+Color
+HoldPkg = pacman glibc
+IgnorePkg = linux vim-youcompleteme*
+ParallelDownloads = 5
+SigLevel = Required DatabaseOptional
+
+[core]
+Include = /etc/pacman.d/mirrorlist
+
+[custom]
+SigLevel = Optional TrustAll
+Server = file:///home/custompkgs
+"
+        );
+        assert_eq!(
+            rendered.mirrorlists,
+            vec![(
+                "/etc/pacman.d/mirrorlist".to_string(),
+                "Server = https://a.example/$repo/os/$arch\nServer = https://b.example/$repo/os/$arch\n"
+                    .to_string()
+            )]
+        );
+
+        let config: Config = rendered.pacman_conf.parse().unwrap();
+        assert!(config.color);
+        assert_eq!(config.parallel_downloads, 5);
+        assert_eq!(config.repos.len(), 2);
+    }
+
+    #[test]
+    fn validate() {
+        let invalid = |s: &str| {
+            let declaration: Declaration = toml::from_str(s).unwrap();
+            declaration.validate().unwrap_err()
+        };
+
+        let err = invalid("[options]\nColour = true");
+        assert!(matches!(err.kind, ErrorKind::UnknownKey(_, k) if k == "Colour"));
+        let err = invalid("[options]\nColor = \"yes\"");
+        assert!(matches!(err.kind, ErrorKind::InvalidValue(_, k, _) if k == "Color"));
+        let err = invalid("[options]\nDBPath = true");
+        assert!(matches!(err.kind, ErrorKind::MissingValue(_, k) if k == "DBPath"));
+        let err = invalid("[options]\nParallelDownloads = \"many\"");
+        assert!(matches!(err.kind, ErrorKind::InvalidValue(_, k, _) if k == "ParallelDownloads"));
+        let err = invalid("[options]\nParallelDownloads = 0");
+        assert!(matches!(err.kind, ErrorKind::InvalidValue(_, _, v) if v == "0"));
+        let err = invalid("[options]\nDBPath = [\"/a\", \"/b\"]");
+        assert!(matches!(err.kind, ErrorKind::InvalidValue(_, k, _) if k == "DBPath"));
+        let err = invalid("[options]\nSigLevel = \"Sometimes\"");
+        assert!(matches!(err.kind, ErrorKind::InvalidValue(_, k, _) if k == "SigLevel"));
+        let err = invalid("[options]\nXferCommand = \"a\\n[evil]\"");
+        assert!(matches!(err.kind, ErrorKind::InvalidValue(_, k, _) if k == "XferCommand"));
+        let err = invalid("[options]\nInclude = \"/etc/pacman.d/extra.conf\"");
+        assert!(matches!(err.kind, ErrorKind::UnknownKey(_, k) if k == "Include"));
+        let err = invalid("[[repos]]\nname = \"options\"");
+        assert!(matches!(err.kind, ErrorKind::InvalidValue(_, _, v) if v == "options"));
+        let err = invalid("[[repos]]\nname = \"core\"\n[[repos]]\nname = \"core\"");
+        assert!(matches!(err.kind, ErrorKind::InvalidValue(_, _, v) if v == "core"));
+        let err = invalid("[[repos]]\nname = \"core\"\nusage = [\"Often\"]");
+        assert!(matches!(err.kind, ErrorKind::InvalidValue(_, k, _) if k == "Usage"));
+
+        assert!(toml::from_str::<Declaration>("[[repos]]\nname = \"core\"\nserver = []").is_err());
+    }
+}
@@ -0,0 +1,403 @@
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{Config, Repository};
+
+/// A change to an option that holds a single value.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Change {
+    /// The key of the option
+    pub key: String,
+    /// The old value
+    pub old: String,
+    /// The new value
+    pub new: String,
+}
+
+/// A change to an option that holds a list of values.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ListChange {
+    /// The key of the option
+    pub key: String,
+    /// Values that were added
+    pub added: Vec<String>,
+    /// Values that were removed
+    pub removed: Vec<String>,
+    /// Whether the values present in both lists are in a different order
+    pub reordered: bool,
+}
+
+/// The changes within a repository present in both configs.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RepoDiff {
+    /// The name of the repository
+    pub name: String,
+    /// Changes to the repository's Server, SigLevel and Usage
+    pub lists: Vec<ListChange>,
+}
+
+/// The structured difference between two configs.
+///
+/// See [`Config::diff`].
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Diff {
+    /// Options holding a single value that changed
+    pub changed: Vec<Change>,
+    /// Options holding a list of values that changed
+    pub lists: Vec<ListChange>,
+    /// Repositories that were added
+    pub added_repos: Vec<String>,
+    /// Repositories that were removed
+    pub removed_repos: Vec<String>,
+    /// If the repositories present in both configs are in a different order, this holds
+    /// their old and new order
+    pub reordered_repos: Option<(Vec<String>, Vec<String>)>,
+    /// Changes within repositories present in both configs
+    pub repos: Vec<RepoDiff>,
+}
+
+impl Diff {
+    /// Returns true if there are no differences.
+    pub fn is_empty(&self) -> bool {
+        self == &Diff::default()
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for change in &self.changed {
+            writeln!(fmt, "~ {}: {} -> {}", change.key, change.old, change.new)?;
+        }
+        for list in &self.lists {
+            fmt_list(fmt, "", list)?;
+        }
+        for repo in &self.added_repos {
+            writeln!(fmt, "+ [{}]", repo)?;
+        }
+        for repo in &self.removed_repos {
+            writeln!(fmt, "- [{}]", repo)?;
+        }
+        if let Some((old, new)) = &self.reordered_repos {
+            writeln!(
+                fmt,
+                "~ repos reordered: {} -> {}",
+                old.join(" "),
+                new.join(" ")
+            )?;
+        }
+        for repo in &self.repos {
+            let prefix = format!("[{}] ", repo.name);
+            for list in &repo.lists {
+                fmt_list(fmt, &prefix, list)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn fmt_list(fmt: &mut fmt::Formatter, prefix: &str, list: &ListChange) -> fmt::Result {
+    for value in &list.added {
+        writeln!(fmt, "+ {}{} = {}", prefix, list.key, value)?;
+    }
+    for value in &list.removed {
+        writeln!(fmt, "- {}{} = {}", prefix, list.key, value)?;
+    }
+    if list.reordered {
+        writeln!(fmt, "~ {}{} reordered", prefix, list.key)?;
+    }
+    Ok(())
+}
+
+fn values(config: &Config) -> Vec<(&'static str, String)> {
+    vec![
+        ("RootDir", config.root_dir.clone()),
+        ("DBPath", config.db_path.clone()),
+        ("GPGDir", config.gpg_dir.clone()),
+        ("LogFile", config.log_file.clone()),
+        ("XferCommand", config.xfer_command.clone()),
+        (
+            "DownloadUser",
+            config.download_user.clone().unwrap_or_default(),
+        ),
+        ("UseSyslog", config.use_syslog.to_string()),
+        ("Color", config.color.to_string()),
+        ("UseDelta", config.use_delta.to_string()),
+        ("TotalDownload", config.total_download.to_string()),
+        ("CheckSpace", config.check_space.to_string()),
+        ("VerbosePkgLists", config.verbose_pkg_lists.to_string()),
+        (
+            "DisableDownloadTimeout",
+            config.disable_download_timeout.to_string(),
+        ),
+        ("ParallelDownloads", config.parallel_downloads.to_string()),
+        ("DisableSandbox", config.disable_sandbox.to_string()),
+        (
+            "DisableSandboxFilesystem",
+            config.disable_sandbox_filesystem.to_string(),
+        ),
+        (
+            "DisableSandboxSyscalls",
+            config.disable_sandbox_syscalls.to_string(),
+        ),
+        ("ILoveCandy", config.chomp.to_string()),
+    ]
+}
+
+pub(crate) fn lists(config: &Config) -> Vec<(&'static str, &[String])> {
+    vec![
+        ("CacheDir", &config.cache_dir),
+        ("HookDir", &config.hook_dir),
+        ("HoldPkg", &config.hold_pkg),
+        ("IgnorePkg", &config.ignore_pkg),
+        ("IgnoreGroup", &config.ignore_group),
+        ("Architecture", &config.architecture),
+        ("NoUpgrade", &config.no_upgrade),
+        ("NoExtract", &config.no_extract),
+        ("CleanMethod", &config.clean_method),
+        ("SigLevel", &config.sig_level),
+        ("LocalFileSigLevel", &config.local_file_sig_level),
+        ("RemoteFileSigLevel", &config.remote_file_sig_level),
+    ]
+}
+
+pub(crate) fn repo_lists(repo: &Repository) -> Vec<(&'static str, &[String])> {
+    vec![
+        ("Server", &repo.servers),
+        ("SigLevel", &repo.sig_level),
+        ("Usage", &repo.usage),
+    ]
+}
+
+/// Returns the values of `old` that are also in `new`, in order.
+fn common<'a>(old: &'a [String], new: &[String]) -> Vec<&'a String> {
+    old.iter().filter(|v| new.contains(v)).collect()
+}
+
+fn diff_list(key: &str, old: &[String], new: &[String]) -> Option<ListChange> {
+    let change = ListChange {
+        key: key.into(),
+        added: new.iter().filter(|v| !old.contains(v)).cloned().collect(),
+        removed: old.iter().filter(|v| !new.contains(v)).cloned().collect(),
+        reordered: common(old, new) != common(new, old),
+    };
+
+    if change.added.is_empty() && change.removed.is_empty() && !change.reordered {
+        None
+    } else {
+        Some(change)
+    }
+}
+
+fn diff_lists(
+    old: Vec<(&'static str, &[String])>,
+    new: Vec<(&'static str, &[String])>,
+) -> Vec<ListChange> {
+    old.into_iter()
+        .zip(new)
+        .filter_map(|((key, old), (_, new))| diff_list(key, old, new))
+        .collect()
+}
+
+impl Config {
+    /// Computes the difference between this config and another.
+    ///
+    /// Options are reported using their pacman.conf key names. The diff can be printed
+    /// using its Display implementation, or serialized when the `serde` feature is enabled.
+    ///
+    /// ```
+    /// use pacmanconf::Config;
+    ///
+    /// let old: Config = "[options]\nIgnorePkg = vim\n[core]\n[extra]".parse().unwrap();
+    /// let new: Config = "[options]\nIgnorePkg = linux\nColor\n[extra]\n[core]".parse().unwrap();
+    ///
+    /// let diff = old.diff(&new);
+    /// assert_eq!(diff.to_string(), "\
+    /// ~ Color: false -> true
+    /// + IgnorePkg = linux
+    /// - IgnorePkg = vim
+    /// ~ repos reordered: core extra -> extra core
+    /// ");
+    /// ```
+    pub fn diff(&self, other: &Config) -> Diff {
+        let changed = values(self)
+            .into_iter()
+            .zip(values(other))
+            .filter(|(old, new)| old.1 != new.1)
+            .map(|((key, old), (_, new))| Change {
+                key: key.into(),
+                old,
+                new,
+            })
+            .collect();
+
+        let old_names = self
+            .repos
+            .iter()
+            .map(|r| r.name.clone())
+            .collect::<Vec<_>>();
+        let new_names = other
+            .repos
+            .iter()
+            .map(|r| r.name.clone())
+            .collect::<Vec<_>>();
+
+        let old_order = common(&old_names, &new_names);
+        let new_order = common(&new_names, &old_names);
+        let reordered_repos = if old_order != new_order {
+            Some((
+                old_order.into_iter().cloned().collect(),
+                new_order.into_iter().cloned().collect(),
+            ))
+        } else {
+            None
+        };
+
+        let repos = self
+            .repos
+            .iter()
+            .filter_map(|old| {
+                let new = other.repos.iter().find(|r| r.name == old.name)?;
+                let lists = diff_lists(repo_lists(old), repo_lists(new));
+                if lists.is_empty() {
+                    None
+                } else {
+                    Some(RepoDiff {
+                        name: old.name.clone(),
+                        lists,
+                    })
+                }
+            })
+            .collect();
+
+        Diff {
+            changed,
+            lists: diff_lists(lists(self), lists(other)),
+            added_repos: new_names
+                .iter()
+                .filter(|r| !old_names.contains(r))
+                .cloned()
+                .collect(),
+            removed_repos: old_names
+                .iter()
+                .filter(|r| !new_names.contains(r))
+                .cloned()
+                .collect(),
+            reordered_repos,
+            repos,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GOLDEN: &str = "
+[options]
+DBPath = /var/lib/pacman/
+HoldPkg = pacman
+HoldPkg = glibc
+IgnorePkg = linux
+[core]
+Server = https://a.example/core
+Server = https://b.example/core
+[extra]
+Server = https://a.example/extra
+[testing]
+Server = https://a.example/testing
+";
+
+    const HOST: &str = "
+[options]
+DBPath = /srv/pacman/
+HoldPkg = glibc
+HoldPkg = pacman
+IgnorePkg = linux
+IgnorePkg = vim
+[extra]
+Server = https://a.example/extra
+[core]
+Server = https://b.example/core
+Server = https://c.example/core
+[multilib]
+Server = https://a.example/multilib
+";
+
+    #[test]
+    fn diff() {
+        let golden: Config = GOLDEN.parse().unwrap();
+        let host: Config = HOST.parse().unwrap();
+
+        assert!(golden.diff(&golden).is_empty());
+
+        let diff = golden.diff(&host);
+        assert_eq!(
+            diff,
+            Diff {
+                changed: vec![Change {
+                    key: "DBPath".into(),
+                    old: "/var/lib/pacman/".into(),
+                    new: "/srv/pacman/".into(),
+                }],
+                lists: vec![
+                    ListChange {
+                        key: "HoldPkg".into(),
+                        reordered: true,
+                        ..Default::default()
+                    },
+                    ListChange {
+                        key: "IgnorePkg".into(),
+                        added: vec!["vim".into()],
+                        ..Default::default()
+                    },
+                ],
+                added_repos: vec!["multilib".into()],
+                removed_repos: vec!["testing".into()],
+                reordered_repos: Some((
+                    vec!["core".into(), "extra".into()],
+                    vec!["extra".into(), "core".into()]
+                )),
+                repos: vec![RepoDiff {
+                    name: "core".into(),
+                    lists: vec![ListChange {
+                        key: "Server".into(),
+                        added: vec!["https://c.example/core".into()],
+                        removed: vec!["https://a.example/core".into()],
+                        reordered: false,
+                    }],
+                }],
+            }
+        );
+
+        assert_eq!(
+            diff.to_string(),
+            "\
+~ DBPath: /var/lib/pacman/ -> /srv/pacman/
+~ HoldPkg reordered
++ IgnorePkg = vim
++ [multilib]
+- [testing]
+~ repos reordered: core extra -> extra core
++ [core] Server = https://c.example/core
+- [core] Server = https://a.example/core
+"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize() {
+        let golden: Config = GOLDEN.parse().unwrap();
+        let host: Config = HOST.parse().unwrap();
+        let diff = golden.diff(&host);
+
+        let json = serde_json::to_string(&diff).unwrap();
+        assert_eq!(serde_json::from_str::<Diff>(&json).unwrap(), diff);
+    }
+}
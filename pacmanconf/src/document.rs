@@ -0,0 +1,608 @@
+use cini::{Callback, CallbackKind, Ini};
+use std::convert::Infallible;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+
+use crate::error::{Error, ErrorKind};
+use crate::keys;
+use crate::Config;
+
+/// The parsed form of a single line in a [`Document`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum LineKind {
+    Section(String),
+    Directive(String, Option<String>),
+}
+
+/// A classified line of a [`Document`].
+///
+/// Commented out lines only have a kind if they would be valid once uncommented.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Line {
+    pub commented: bool,
+    pub section: Option<String>,
+    pub kind: Option<LineKind>,
+}
+
+struct Classifier {
+    commented: bool,
+    kind: Option<LineKind>,
+}
+
+impl Ini for Classifier {
+    type Err = Infallible;
+
+    fn callback(&mut self, cb: Callback) -> Result<(), Self::Err> {
+        let commented = self.commented;
+
+        self.kind = match cb.kind {
+            CallbackKind::Section(section) if !commented || keys::is_repo_name(section) => {
+                Some(LineKind::Section(section.into()))
+            }
+            CallbackKind::Directive(section, key, value)
+                if !commented
+                    || section
+                        .and_then(|section| keys::directive(section, key))
+                        .is_some_and(|v| v.accepts(value)) =>
+            {
+                Some(LineKind::Directive(key.into(), value.map(Into::into)))
+            }
+            _ => None,
+        };
+
+        Ok(())
+    }
+}
+
+/// Splits the comment characters from the start of a line.
+fn uncomment(line: &str) -> (bool, &str) {
+    let trimmed = line.trim_start();
+    match trimmed.strip_prefix('#') {
+        Some(rest) => (true, rest.trim_start_matches('#')),
+        None => (false, trimmed),
+    }
+}
+
+/// Classifies every line of an ini str.
+///
+/// Active lines are tracked against the last active section while commented out lines are
+/// tracked against the last section header, commented or not. This means a disabled
+/// `#[multilib]` section owns the `#Include` line that follows it.
+pub(crate) fn classify(ini: &str) -> Vec<Line> {
+    let mut classifier = Classifier {
+        commented: false,
+        kind: None,
+    };
+    let mut active = None;
+    let mut any = None;
+    let mut lines = Vec::new();
+
+    for (line_number, raw) in ini.lines().enumerate() {
+        let (commented, text) = uncomment(raw);
+        let current = if commented { any } else { active };
+        classifier.commented = commented;
+        classifier.kind = None;
+
+        let section = match classifier.parse_line(None, text, line_number, current) {
+            Ok(section) => section,
+            Err(never) => match never {},
+        };
+
+        let section = if classifier.kind.is_some() {
+            if !commented {
+                active = section;
+            }
+            any = section;
+            section
+        } else {
+            None
+        };
+
+        lines.push(Line {
+            commented,
+            section: section.map(Into::into),
+            kind: classifier.kind.take(),
+        });
+    }
+
+    lines
+}
+
+/// A pacman.conf that can be edited in place.
+///
+/// Unlike [`Config`], a document keeps every line of the original file, including comments
+/// and formatting. Edits follow pacman.conf's own conventions: disabled options and
+/// repositories are commented out with `#` and enabling them again uncomments the existing
+/// lines. Only the lines affected by an edit are changed.
+///
+/// ```
+/// use pacmanconf::Document;
+///
+/// let mut doc: Document = "
+/// [options]
+/// #Color
+/// #ParallelDownloads = 5
+///
+/// #[multilib]
+/// #Include = /etc/pacman.d/mirrorlist
+/// ".parse().unwrap();
+///
+/// doc.set_option("Color", None).unwrap();
+/// doc.set_option("ParallelDownloads", "10").unwrap();
+/// doc.enable_repo("multilib").unwrap();
+///
+/// assert_eq!(doc.to_string(), "
+/// [options]
+/// Color
+/// ParallelDownloads = 10
+///
+/// [multilib]
+/// Include = /etc/pacman.d/mirrorlist
+/// ");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Document {
+    filename: Option<String>,
+    lines: Vec<String>,
+    newline: bool,
+}
+
+impl FromStr for Document {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Document::parse(None, s))
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for (i, line) in self.lines.iter().enumerate() {
+            if i != 0 {
+                fmt.write_str("\n")?;
+            }
+            fmt.write_str(line)?;
+        }
+        if self.newline {
+            fmt.write_str("\n")?;
+        }
+        Ok(())
+    }
+}
+
+impl Document {
+    /// Creates a new Document from a str. Optionally a filename can be supplied, this is
+    /// recorded so that the document can later be written back.
+    pub fn parse(filename: Option<&str>, ini: &str) -> Document {
+        Document {
+            filename: filename.map(Into::into),
+            lines: ini.lines().map(Into::into).collect(),
+            newline: ini.ends_with('\n'),
+        }
+    }
+
+    /// Creates a new Document from a file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Document, Error> {
+        let path = path.as_ref();
+        let ini = fs::read_to_string(path)?;
+        Ok(Document::parse(Some(&path.to_string_lossy()), &ini))
+    }
+
+    /// The filename of the document (if any).
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    /// Writes the document back to the file it was read from.
+    ///
+    /// See [`save_as`](Document::save_as) for how the file is written.
+    pub fn save(&self) -> Result<(), Error> {
+        let filename = self.filename.as_deref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "document has no filename")
+        })?;
+        self.save_as(filename)
+    }
+
+    /// Writes the document to a file.
+    ///
+    /// The file is replaced atomically by writing to a temporary file in the same directory
+    /// and renaming it over the original. The permissions of the original file are kept and
+    /// symlinks are followed so that the file they point to is replaced.
+    pub fn save_as<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let tmp = dir.join(format!(".{}.{}.tmp", name, process::id()));
+
+        let res = (|| {
+            let mut file = OpenOptions::new().write(true).create_new(true).open(&tmp)?;
+            if let Ok(metadata) = fs::metadata(&path) {
+                file.set_permissions(metadata.permissions())?;
+            }
+            file.write_all(self.to_string().as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&tmp, &path)
+        })();
+
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        Ok(res?)
+    }
+
+    /// Enables a repository by uncommenting its section and the directives within it.
+    ///
+    /// One `#` is removed from each directive, so a directive that was already commented
+    /// out when the repository was disabled stays commented out.
+    ///
+    /// Returns false if the repository is already enabled.
+    pub fn enable_repo(&mut self, name: &str) -> Result<bool, Error> {
+        let lines = self.classify();
+
+        if find_section(&lines, false, name).is_some() {
+            return Ok(false);
+        }
+
+        let start =
+            find_section(&lines, true, name).ok_or_else(|| ErrorKind::NoRepo(name.into()))?;
+        self.uncomment_line(start);
+
+        for (i, line) in lines.iter().enumerate().skip(start + 1) {
+            match line.kind {
+                Some(LineKind::Section(_)) => break,
+                Some(LineKind::Directive(..)) if line.commented => self.strip_comment(i),
+                _ => (),
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Disables a repository by commenting out its section and the directives within it.
+    ///
+    /// Directives that are already commented out get another `#` so that
+    /// [`enable_repo`](Document::enable_repo) leaves them as they were.
+    ///
+    /// Returns false if the repository is already disabled.
+    pub fn disable_repo(&mut self, name: &str) -> Result<bool, Error> {
+        let lines = self.classify();
+
+        let start = match find_section(&lines, false, name) {
+            Some(start) => start,
+            None if find_section(&lines, true, name).is_some() => return Ok(false),
+            None => return Err(ErrorKind::NoRepo(name.into()).into()),
+        };
+        self.comment_line(start);
+
+        for (i, line) in lines.iter().enumerate().skip(start + 1) {
+            match line.kind {
+                Some(LineKind::Section(_)) if !line.commented => break,
+                Some(LineKind::Directive(..)) if line.section.as_deref() == Some(name) => {
+                    self.comment_line(i)
+                }
+                _ => (),
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Sets an option in the `[options]` section.
+    ///
+    /// Options that are flags such as `Color` take `None` as their value.
+    ///
+    /// If the option is already set the first occurrence is updated and any later
+    /// occurrences are commented out. Otherwise a commented out occurrence is
+    /// uncommented, preferring one that already has the wanted value. Failing that,
+    /// the option is added to the end of the `[options]` section.
+    ///
+    /// Returns false if the option was already set to this value.
+    pub fn set_option<'a, V: Into<Option<&'a str>>>(
+        &mut self,
+        key: &str,
+        value: V,
+    ) -> Result<bool, Error> {
+        let value = value.into();
+        validate_option(key, value)?;
+
+        let lines = self.classify();
+        let options = |commented: bool| {
+            lines
+                .iter()
+                .enumerate()
+                .filter(move |(_, line)| {
+                    line.commented == commented && line.section.as_deref() == Some("options")
+                })
+                .filter_map(|(i, line)| match &line.kind {
+                    Some(LineKind::Directive(k, v)) if k == key => Some((i, v.as_deref())),
+                    _ => None,
+                })
+        };
+
+        let mut active = options(false);
+
+        if let Some((first, current)) = active.next() {
+            let mut changed = false;
+            if current != value {
+                self.set_value(first, key, value);
+                changed = true;
+            }
+            for (i, _) in active {
+                self.comment_line(i);
+                changed = true;
+            }
+            return Ok(changed);
+        }
+
+        let commented = options(true)
+            .find(|&(_, v)| v == value)
+            .or_else(|| options(true).next());
+
+        if let Some((i, _)) = commented {
+            self.uncomment_line(i);
+            self.set_value(i, key, value);
+            return Ok(true);
+        }
+
+        let line = match value {
+            Some(value) => format!("{} = {}", key, value),
+            None => key.to_string(),
+        };
+
+        match find_section(&lines, false, "options") {
+            Some(start) => {
+                let end = lines
+                    .iter()
+                    .enumerate()
+                    .skip(start + 1)
+                    .take_while(|(_, l)| {
+                        l.commented || !matches!(l.kind, Some(LineKind::Section(_)))
+                    })
+                    .filter(|(_, l)| !l.commented && l.kind.is_some())
+                    .map(|(i, _)| i)
+                    .last()
+                    .unwrap_or(start);
+                self.lines.insert(end + 1, line);
+            }
+            None => {
+                let start = lines
+                    .iter()
+                    .position(|l| !l.commented && l.kind.is_some())
+                    .unwrap_or(self.lines.len());
+                self.lines.insert(start, line);
+                self.lines.insert(start, "[options]".into());
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Unsets an option in the `[options]` section by commenting out every occurrence of it.
+    ///
+    /// Returns false if the option was not set.
+    pub fn unset_option(&mut self, key: &str) -> Result<bool, Error> {
+        if keys::option_key(key).is_none() {
+            return Err(ErrorKind::UnknownKey("options".into(), key.into()).into());
+        }
+
+        let lines = self.classify();
+        let mut changed = false;
+
+        for (i, line) in lines.iter().enumerate() {
+            if line.commented || line.section.as_deref() != Some("options") {
+                continue;
+            }
+            if matches!(&line.kind, Some(LineKind::Directive(k, _)) if k == key) {
+                self.comment_line(i);
+                changed = true;
+            }
+        }
+
+        Ok(changed)
+    }
+
+    pub(crate) fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    pub(crate) fn classify(&self) -> Vec<Line> {
+        classify(&self.to_string())
+    }
+
+    pub(crate) fn insert_line(&mut self, i: usize, line: String) {
+        self.lines.insert(i, line);
+    }
+
+    pub(crate) fn insert_lines(&mut self, i: usize, lines: Vec<String>) {
+        self.lines.splice(i..i, lines);
+    }
+
+    pub(crate) fn remove_lines(&mut self, range: RangeInclusive<usize>) -> Vec<String> {
+        self.lines.drain(range).collect()
+    }
+
+    pub(crate) fn comment_line(&mut self, i: usize) {
+        self.lines[i].insert(0, '#');
+    }
+
+    /// Removes a single `#` from a commented out line.
+    fn strip_comment(&mut self, i: usize) {
+        let line = &self.lines[i];
+        let indent = line.len() - line.trim_start().len();
+        self.lines[i].remove(indent);
+    }
+
+    pub(crate) fn uncomment_line(&mut self, i: usize) {
+        let line = &self.lines[i];
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let (_, text) = uncomment(line);
+        self.lines[i] = format!("{}{}", indent, text);
+    }
+
+    /// Replaces the value of a directive while keeping the key and its alignment.
+    pub(crate) fn set_value(&mut self, i: usize, key: &str, value: Option<&str>) {
+        let line = &self.lines[i];
+        let start = line.find(key).unwrap_or(0);
+        let key_end = start + key.len();
+
+        self.lines[i] = match (value, line[key_end..].find('=')) {
+            (Some(value), Some(eq)) => {
+                let eq = key_end + eq + 1;
+                let rest = &line[eq..];
+                let space = &rest[..rest.len() - rest.trim_start().len()];
+                let space = if space.is_empty() { " " } else { space };
+                format!("{}{}{}", &line[..eq], space, value)
+            }
+            (Some(value), None) => format!("{} = {}", &line[..key_end], value),
+            (None, _) => line[..key_end].to_string(),
+        };
+    }
+}
+
+pub(crate) fn find_section(lines: &[Line], commented: bool, name: &str) -> Option<usize> {
+    lines.iter().position(|line| {
+        line.commented == commented && matches!(&line.kind, Some(LineKind::Section(s)) if s == name)
+    })
+}
+
+pub(crate) fn validate_option(key: &str, value: Option<&str>) -> Result<(), Error> {
+    let section = "options";
+    let kind = match keys::option_key(key) {
+        Some(kind) if key != "Include" => kind,
+        _ => return Err(ErrorKind::UnknownKey(section.into(), key.into()).into()),
+    };
+
+    if !kind.accepts(value) {
+        let kind = match value {
+            Some(value) => ErrorKind::InvalidValue(section.into(), key.into(), value.into()),
+            None => ErrorKind::MissingValue(section.into(), key.into()),
+        };
+        return Err(kind.into());
+    }
+
+    let mut config = Config::default();
+    config.handle_option(section, key, value)?;
+    // pacman only rejects this once the whole config has been read
+    if key == "ParallelDownloads" && config.parallel_downloads == 0 {
+        let value = value.unwrap_or_default().into();
+        return Err(ErrorKind::InvalidValue(section.into(), key.into(), value).into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACMAN_CONF: &str = "\
+[options]
+HoldPkg     = pacman glibc
+#CleanMethod = KeepInstalled
+#Color
+#ParallelDownloads = 5
+Architecture = auto
+
+#[core-testing]
+#Include = /etc/pacman.d/mirrorlist
+
+[core]
+Include = /etc/pacman.d/mirrorlist
+
+# An example of a custom package repository.
+#[custom]
+#SigLevel = Optional TrustAll
+#Server = file:///home/custompkgs
+";
+
+    #[test]
+    fn round_trip() {
+        let doc: Document = PACMAN_CONF.parse().unwrap();
+        assert_eq!(doc.to_string(), PACMAN_CONF);
+
+        let doc: Document = "[options]\nColor".parse().unwrap();
+        assert_eq!(doc.to_string(), "[options]\nColor");
+    }
+
+    #[test]
+    fn repos() {
+        let mut doc: Document = PACMAN_CONF.parse().unwrap();
+
+        assert!(doc.enable_repo("custom").unwrap());
+        assert!(!doc.enable_repo("custom").unwrap());
+        assert!(doc.disable_repo("core").unwrap());
+        assert!(!doc.disable_repo("core").unwrap());
+        assert!(doc.enable_repo("nope").is_err());
+
+        let expected = PACMAN_CONF
+            .replace("[core]\nInclude", "#[core]\n#Include")
+            .replace("#[custom]", "[custom]")
+            .replace("#SigLevel", "SigLevel")
+            .replace("#Server", "Server");
+        assert_eq!(doc.to_string(), expected);
+
+        assert!(doc.enable_repo("core").unwrap());
+        assert!(doc.disable_repo("custom").unwrap());
+        assert_eq!(doc.to_string(), PACMAN_CONF);
+
+        // an alternative kept commented out stays that way
+        let ini = "[custom]\n#Server = file:///old\nServer = file:///new\n";
+        let mut doc: Document = ini.parse().unwrap();
+        assert!(doc.disable_repo("custom").unwrap());
+        assert_eq!(
+            doc.to_string(),
+            "#[custom]\n##Server = file:///old\n#Server = file:///new\n"
+        );
+        assert!(doc.enable_repo("custom").unwrap());
+        assert_eq!(doc.to_string(), ini);
+    }
+
+    #[test]
+    fn options() {
+        let mut doc: Document = PACMAN_CONF.parse().unwrap();
+
+        assert!(doc.set_option("ParallelDownloads", "10").unwrap());
+        assert!(doc.set_option("Color", None).unwrap());
+        assert!(!doc.set_option("Color", None).unwrap());
+        assert!(doc.set_option("HoldPkg", "pacman").unwrap());
+        assert!(doc.set_option("DownloadUser", "alpm").unwrap());
+        assert!(doc.unset_option("Architecture").unwrap());
+        assert!(!doc.unset_option("UseSyslog").unwrap());
+
+        assert!(doc.set_option("ParallelDownloads", "lots").is_err());
+        assert!(doc.set_option("ParallelDownloads", "0").is_err());
+        assert!(doc.set_option("Color", "yes").is_err());
+        assert!(doc.set_option("DBPath", None).is_err());
+        assert!(doc.set_option("Include", "foo").is_err());
+        assert!(doc.unset_option("Cake").is_err());
+
+        let expected = PACMAN_CONF
+            .replace("pacman glibc", "pacman")
+            .replace("#Color", "Color")
+            .replace("#ParallelDownloads = 5", "ParallelDownloads = 10")
+            .replace(
+                "Architecture = auto\n",
+                "#Architecture = auto\nDownloadUser = alpm\n",
+            );
+        assert_eq!(doc.to_string(), expected);
+    }
+
+    #[test]
+    fn no_options() {
+        let mut doc: Document = "# comment\n[core]\nServer = foo\n".parse().unwrap();
+        doc.set_option("Color", None).unwrap();
+        assert_eq!(
+            doc.to_string(),
+            "# comment\n[options]\nColor\n[core]\nServer = foo\n"
+        );
+    }
+}
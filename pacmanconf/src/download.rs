@@ -0,0 +1,679 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::error::{Error, ErrorKind};
+use crate::xfer::{XferCommand, STALL_TIMEOUT};
+use crate::{Config, Repository};
+
+/// How many redirects are followed before giving up.
+const MAX_REDIRECTS: usize = 10;
+
+/// A file to download and the mirrors it can be downloaded from.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Download {
+    /// The URLs to try, in order
+    pub urls: Vec<String>,
+    /// Where to save the file
+    pub dest: PathBuf,
+}
+
+impl Download {
+    /// A file in a repository, such as `core.db` or a package, downloaded from each of the
+    /// repository's servers in turn to `dir`.
+    ///
+    /// Servers are used as they are, so `$repo` and `$arch` should already be replaced as
+    /// they are by pacman-conf.
+    pub fn from_repo<P: AsRef<Path>>(repo: &Repository, filename: &str, dir: P) -> Download {
+        Download {
+            urls: repo
+                .servers
+                .iter()
+                .map(|server| format!("{}/{}", server.trim_end_matches('/'), filename))
+                .collect(),
+            dest: dir.as_ref().join(filename),
+        }
+    }
+
+    /// The detached signature of the file, with `.sig` appended to the URLs and the
+    /// destination.
+    pub fn signature(&self) -> Download {
+        let mut dest = self.dest.as_os_str().to_os_string();
+        dest.push(".sig");
+        Download {
+            urls: self.urls.iter().map(|url| format!("{}.sig", url)).collect(),
+            dest: dest.into(),
+        }
+    }
+}
+
+/// Downloads files the way pacman does with a given config.
+///
+/// Each file is tried from its mirrors in order until one succeeds, and up to
+/// ParallelDownloads files are downloaded at once. If the config sets an XferCommand it is
+/// used to download every file, run as the DownloadUser. Otherwise a built in downloader
+/// is used, which supports `file://` and plain `http://` URLs.
+///
+/// The built in downloader runs in this process and so can not drop privileges to the
+/// DownloadUser, callers running as root should set an XferCommand or drop privileges
+/// themselves if that matters.
+///
+/// Transfers that receive no data for [`STALL_TIMEOUT`] are abandoned and the next mirror
+/// is tried, unless DisableDownloadTimeout is set.
+///
+/// ```no_run
+/// use pacmanconf::{Config, Download, Downloader};
+///
+/// # fn main() -> Result<(), pacmanconf::Error> {
+/// let config = Config::new()?;
+/// let downloader = Downloader::new(&config)?;
+///
+/// let downloads = config
+///     .repos
+///     .iter()
+///     .map(|repo| Download::from_repo(repo, &format!("{}.db", repo.name), "/tmp"))
+///     .collect::<Vec<_>>();
+/// for (download, result) in downloads.iter().zip(downloader.fetch_all(&downloads)) {
+///     if let Err(err) = result {
+///         eprintln!("{}: {}", download.dest.display(), err);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Downloader {
+    xfer: Option<XferCommand>,
+    parallel_downloads: usize,
+    stall_timeout: Option<Duration>,
+}
+
+impl Default for Downloader {
+    fn default() -> Self {
+        Downloader {
+            xfer: None,
+            parallel_downloads: 1,
+            stall_timeout: Some(STALL_TIMEOUT),
+        }
+    }
+}
+
+impl Downloader {
+    /// A downloader that follows a config's XferCommand, DownloadUser,
+    /// DisableDownloadTimeout and ParallelDownloads.
+    pub fn new(config: &Config) -> Result<Downloader, Error> {
+        let mut downloader = Downloader::default();
+        downloader
+            .xfer_command(XferCommand::from_config(config)?)
+            .parallel_downloads(config.parallel_downloads as usize);
+        if config.disable_download_timeout {
+            downloader.stall_timeout(None);
+        }
+        Ok(downloader)
+    }
+
+    /// Sets how many files are downloaded at once. Zero is treated as one.
+    pub fn parallel_downloads(&mut self, n: usize) -> &mut Self {
+        self.parallel_downloads = n.max(1);
+        self
+    }
+
+    /// Sets how long a transfer may go without receiving data before it is abandoned.
+    /// None waits forever.
+    ///
+    /// This also applies to the XferCommand.
+    pub fn stall_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.stall_timeout = timeout;
+        if let Some(xfer) = &mut self.xfer {
+            xfer.stall_timeout(timeout);
+        }
+        self
+    }
+
+    /// Sets the command used to download files instead of the built in downloader.
+    pub fn xfer_command(&mut self, xfer: Option<XferCommand>) -> &mut Self {
+        self.xfer = xfer;
+        if let Some(xfer) = &mut self.xfer {
+            xfer.stall_timeout(self.stall_timeout);
+        }
+        self
+    }
+
+    /// Downloads a file, trying each of its URLs in order.
+    ///
+    /// The file is written to its destination with `.part` appended and renamed once it
+    /// is complete, so the destination never holds a partial download.
+    pub fn fetch(&self, download: &Download) -> Result<(), Error> {
+        let name = download
+            .dest
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut reasons = Vec::new();
+
+        for url in &download.urls {
+            let result = match &self.xfer {
+                Some(xfer) => xfer.run(url, &download.dest).map_err(|e| match e.kind {
+                    ErrorKind::Download(reason) => reason,
+                    kind => format!("{}: {}", url, Error::from(kind)),
+                }),
+                None => self.fetch_url(url, &download.dest),
+            };
+            match result {
+                Ok(()) => return Ok(()),
+                Err(reason) => reasons.push(reason),
+            }
+        }
+
+        if reasons.is_empty() {
+            reasons.push("no servers configured".into());
+        }
+        let reason = format!(
+            "failed retrieving file '{}' from all mirrors: {}",
+            name,
+            reasons.join("; ")
+        );
+        Err(ErrorKind::Download(reason).into())
+    }
+
+    /// Downloads files, up to ParallelDownloads at a time.
+    ///
+    /// The results are in the same order as the downloads.
+    pub fn fetch_all(&self, downloads: &[Download]) -> Vec<Result<(), Error>> {
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(Vec::with_capacity(downloads.len()));
+        let workers = self.parallel_downloads.min(downloads.len());
+
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let download = match downloads.get(i) {
+                        Some(download) => download,
+                        None => break,
+                    };
+                    let result = self.fetch(download);
+                    results.lock().unwrap().push((i, result));
+                });
+            }
+        });
+
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|(i, _)| *i);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    /// Downloads a single URL with the built in downloader.
+    fn fetch_url(&self, url: &str, dest: &Path) -> Result<(), String> {
+        let mut part = dest.as_os_str().to_os_string();
+        part.push(".part");
+        let part = PathBuf::from(part);
+
+        let result = match url.split_once("://") {
+            Some(("file", path)) => fs::copy(decode(path), &part).map(drop),
+            Some(("http", _)) => File::create(&part).and_then(|mut file| {
+                self.http(url, &mut file)?;
+                file.sync_all()
+            }),
+            Some((scheme, _)) => Err(unsupported(format!("unsupported protocol {}", scheme))),
+            None => Err(unsupported("not a URL".into())),
+        };
+
+        let result = result.and_then(|()| fs::rename(&part, dest));
+        result.map_err(|e| {
+            let _ = fs::remove_file(&part);
+            let reason = match e.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                    format!(
+                        "no data received for {:?}",
+                        self.stall_timeout.unwrap_or_default()
+                    )
+                }
+                _ => e.to_string(),
+            };
+            format!("{}: {}", url, reason)
+        })
+    }
+
+    /// Fetches an HTTP URL into `out`, following redirects.
+    fn http(&self, url: &str, out: &mut File) -> io::Result<()> {
+        let mut url = url.to_string();
+
+        for _ in 0..=MAX_REDIRECTS {
+            let (authority, path) = split_url(&url)?;
+            let mut reader = BufReader::new(self.connect(authority)?);
+            write!(
+                reader.get_mut(),
+                "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: pacmanconf/{}\r\n\
+                 Accept: */*\r\nConnection: close\r\n\r\n",
+                path,
+                authority,
+                env!("CARGO_PKG_VERSION")
+            )?;
+
+            let response = Response::read(&mut reader)?;
+            if (300..400).contains(&response.status) {
+                if let Some(location) = response.location {
+                    url = redirect(authority, path, &location);
+                    continue;
+                }
+            }
+            if response.status >= 300 {
+                return Err(unsupported(format!(
+                    "server returned HTTP {}",
+                    response.status
+                )));
+            }
+
+            return if response.chunked {
+                read_chunked(&mut reader, out)
+            } else if let Some(len) = response.content_length {
+                let copied = io::copy(&mut reader.by_ref().take(len), out)?;
+                match copied == len {
+                    true => Ok(()),
+                    false => Err(io::ErrorKind::UnexpectedEof.into()),
+                }
+            } else {
+                io::copy(&mut reader, out).map(drop)
+            };
+        }
+
+        Err(unsupported("too many redirects".into()))
+    }
+
+    fn connect(&self, authority: &str) -> io::Result<TcpStream> {
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                let port = port
+                    .parse()
+                    .map_err(|_| unsupported("invalid port".into()))?;
+                (host, port)
+            }
+            _ => (authority, 80),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+
+        let mut err = io::Error::new(io::ErrorKind::NotFound, "could not resolve host");
+        for addr in (host, port).to_socket_addrs()? {
+            let stream = match self.stall_timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+            match stream {
+                Ok(stream) => {
+                    stream.set_read_timeout(self.stall_timeout)?;
+                    return Ok(stream);
+                }
+                Err(e) => err = e,
+            }
+        }
+        Err(err)
+    }
+}
+
+/// The parts of an HTTP response the downloader cares about.
+struct Response {
+    status: u16,
+    location: Option<String>,
+    content_length: Option<u64>,
+    chunked: bool,
+}
+
+impl Response {
+    fn read<R: BufRead>(reader: &mut R) -> io::Result<Response> {
+        let invalid = || unsupported("invalid HTTP response".into());
+        let status = read_line(reader)?;
+        let status = status
+            .split_whitespace()
+            .nth(1)
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(invalid)?;
+
+        let mut response = Response {
+            status,
+            location: None,
+            content_length: None,
+            chunked: false,
+        };
+        loop {
+            let line = read_line(reader)?;
+            if line.is_empty() {
+                return Ok(response);
+            }
+            let (name, value) = line.split_once(':').ok_or_else(invalid)?;
+            let value = value.trim();
+            match name.to_ascii_lowercase().as_str() {
+                "location" => response.location = Some(value.into()),
+                "content-length" => {
+                    response.content_length = Some(value.parse().map_err(|_| invalid())?)
+                }
+                "transfer-encoding" => {
+                    response.chunked = value.eq_ignore_ascii_case("chunked");
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Reads a line without its line ending, failing if the connection closes first.
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).into())
+}
+
+/// Reads a body sent with `Transfer-Encoding: chunked`.
+fn read_chunked<R: BufRead, W: Write>(reader: &mut R, out: &mut W) -> io::Result<()> {
+    loop {
+        let line = read_line(reader)?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size =
+            u64::from_str_radix(size, 16).map_err(|_| unsupported("invalid chunk size".into()))?;
+        if size == 0 {
+            return Ok(());
+        }
+        if io::copy(&mut reader.take(size), out)? != size {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        read_line(reader)?;
+    }
+}
+
+/// Splits an `http://` URL into its host and path.
+fn split_url(url: &str) -> io::Result<(&str, &str)> {
+    let rest = match url.split_once("://") {
+        Some(("http", rest)) => rest,
+        Some((scheme, _)) => return Err(unsupported(format!("unsupported protocol {}", scheme))),
+        None => return Err(unsupported("not a URL".into())),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    if authority.is_empty() || authority.contains('@') {
+        return Err(unsupported(format!("unsupported host {}", authority)));
+    }
+    Ok((authority, path))
+}
+
+/// Resolves the Location of a redirect against the URL that was requested.
+fn redirect(authority: &str, path: &str, location: &str) -> String {
+    if location.contains("://") {
+        location.into()
+    } else if location.starts_with('/') {
+        format!("http://{}{}", authority, location)
+    } else {
+        let dir = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+        let joined = format!("{}{}", dir, location);
+        let mut segments = Vec::new();
+        for segment in joined.split('/').skip(1) {
+            match segment {
+                "." => (),
+                ".." => drop(segments.pop()),
+                segment => segments.push(segment),
+            }
+        }
+        format!("http://{}/{}", authority, segments.join("/"))
+    }
+}
+
+/// Decodes `%XX` escapes in the path of a `file://` URL.
+fn decode(path: &str) -> PathBuf {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    PathBuf::from(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+fn unsupported(reason: String) -> io::Error {
+    io::Error::other(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::process;
+
+    /// Starts an HTTP server on localhost and returns its address.
+    fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let request = read_line(&mut reader).unwrap();
+                    while !read_line(&mut reader).unwrap().is_empty() {}
+
+                    let path = request.split_whitespace().nth(1).unwrap();
+                    let response: &[u8] = match path {
+                        "/ok/core.db" => b"HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\ndatabase",
+                        "/ok/core.db.sig" => b"HTTP/1.1 200 OK\r\n\r\nsignature",
+                        "/chunked" => {
+                            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+                                        5\r\nhello\r\n6;x=y\r\n world\r\n0\r\n\r\n"
+                        }
+                        "/moved/core.db" => {
+                            b"HTTP/1.1 301 Moved\r\nLocation: ../ok/core.db\r\n\r\n"
+                        }
+                        "/loop" => b"HTTP/1.1 302 Found\r\nLocation: /loop\r\n\r\n",
+                        "/short" => b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\nabc",
+                        "/stall" => {
+                            stream
+                                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\nabc")
+                                .unwrap();
+                            thread::sleep(Duration::from_secs(2));
+                            b""
+                        }
+                        _ => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n",
+                    };
+                    let _ = stream.write_all(response);
+                });
+            }
+        });
+
+        format!("http://{}", addr)
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("pacmanconf-download-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn download(urls: &[String], dest: &Path) -> Download {
+        Download {
+            urls: urls.to_vec(),
+            dest: dest.into(),
+        }
+    }
+
+    #[test]
+    fn http() {
+        let server = serve();
+        let dir = temp_dir("http");
+        let dest = dir.join("file");
+        let downloader = Downloader::default();
+        let fetch = |path: &str| downloader.fetch(&download(&[server.clone() + path], &dest));
+
+        fetch("/ok/core.db").unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "database");
+        fetch("/ok/core.db.sig").unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "signature");
+        fetch("/chunked").unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "hello world");
+        fetch("/moved/core.db").unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "database");
+
+        for (path, reason) in [
+            ("/missing", "HTTP 404"),
+            ("/loop", "too many redirects"),
+            ("/short", ""),
+        ] {
+            let err = fetch(path).unwrap_err().to_string();
+            assert!(err.contains(reason), "{}: {}", path, err);
+        }
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "database");
+        assert!(!dir.join("file.part").exists());
+
+        let err = downloader
+            .fetch(&download(&["https://example.com/core.db".into()], &dest))
+            .unwrap_err();
+        assert!(err.to_string().contains("unsupported protocol https"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failover() {
+        let server = serve();
+        let dir = temp_dir("failover");
+        let repo = Repository {
+            name: "core".into(),
+            servers: vec![server.clone() + "/missing/", server.clone() + "/ok"],
+            ..Repository::default()
+        };
+        let db = Download::from_repo(&repo, "core.db", &dir);
+        assert_eq!(db.urls[0], format!("{}/missing/core.db", server));
+        let downloader = Downloader::default();
+
+        downloader.fetch(&db).unwrap();
+        downloader.fetch(&db.signature()).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("core.db")).unwrap(), "database");
+        assert_eq!(
+            fs::read_to_string(dir.join("core.db.sig")).unwrap(),
+            "signature"
+        );
+
+        let err = downloader
+            .fetch(&Download::from_repo(&repo, "extra.db", &dir))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("failed retrieving file 'extra.db' from all mirrors"));
+        assert!(err.contains("/missing/extra.db"));
+        assert!(err.contains("/ok/extra.db"));
+
+        let repo = Repository::default();
+        let err = downloader.fetch(&Download::from_repo(&repo, "core.db", &dir));
+        assert!(err.unwrap_err().to_string().contains("no servers"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn file() {
+        let dir = temp_dir("file");
+        fs::write(dir.join("a b.pkg"), "package").unwrap();
+        let url = format!("file://{}/a%20b.pkg", dir.display());
+
+        let dest = dir.join("copy");
+        Downloader::default()
+            .fetch(&download(&[url], &dest))
+            .unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "package");
+
+        let url = format!("file://{}/missing", dir.display());
+        assert!(Downloader::default()
+            .fetch(&download(&[url], &dest))
+            .is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn stall() {
+        let server = serve();
+        let dir = temp_dir("stall");
+        let mut downloader = Downloader::default();
+        downloader.stall_timeout(Some(Duration::from_millis(200)));
+
+        let err = downloader
+            .fetch(&download(&[server + "/stall"], &dir.join("file")))
+            .unwrap_err();
+        assert!(err.to_string().contains("no data received"), "{}", err);
+        assert!(!dir.join("file").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn fetch_all() {
+        let server = serve();
+        let dir = temp_dir("fetch-all");
+        let downloads = (0..5)
+            .map(|i| {
+                let path = if i == 3 { "/missing" } else { "/ok/core.db" };
+                download(&[server.clone() + path], &dir.join(i.to_string()))
+            })
+            .collect::<Vec<_>>();
+        let mut downloader = Downloader::default();
+        downloader.parallel_downloads(3);
+
+        let results = downloader.fetch_all(&downloads);
+        assert_eq!(results.len(), 5);
+        for (i, result) in results.iter().enumerate() {
+            assert_eq!(result.is_ok(), i != 3);
+        }
+        assert_eq!(fs::read_to_string(dir.join("4")).unwrap(), "database");
+        assert!(downloader.fetch_all(&[]).is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn config() {
+        let dir = temp_dir("config");
+        let config: Config = r#"[options]
+XferCommand = sh -c 'printf "%s" "$0" > "$1"' %u %o
+ParallelDownloads = 0
+DisableDownloadTimeout
+"#
+        .parse()
+        .unwrap();
+        let downloader = Downloader::new(&config).unwrap();
+        assert_eq!(downloader.parallel_downloads, 1);
+        assert_eq!(downloader.stall_timeout, None);
+
+        let dest = dir.join("file");
+        downloader
+            .fetch(&download(&["https://example.com/file".into()], &dest))
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&dest).unwrap(),
+            "https://example.com/file"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,415 @@
+use crate::document::{find_section, validate_option, Line, LineKind};
+use crate::error::{Error, ErrorKind};
+use crate::keys;
+use crate::native::{self, SigLevel};
+use crate::{Document, Repository};
+
+/// The active directives with a key in a section, as their line index and value.
+fn directives(lines: &[Line], section: &str, key: &str) -> Vec<(usize, String)> {
+    lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !line.commented && line.section.as_deref() == Some(section))
+        .filter_map(|(i, line)| match &line.kind {
+            Some(LineKind::Directive(k, v)) if k == key => Some((i, v.clone().unwrap_or_default())),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The last line that belongs to an active section: its last directive, commented or not,
+/// or its header if it has none.
+fn section_end(lines: &[Line], start: usize) -> usize {
+    let name = match &lines[start].kind {
+        Some(LineKind::Section(name)) => name,
+        _ => return start,
+    };
+
+    lines
+        .iter()
+        .enumerate()
+        .skip(start + 1)
+        .take_while(|(_, l)| l.commented || !matches!(l.kind, Some(LineKind::Section(_))))
+        .filter(|(_, l)| matches!(l.kind, Some(LineKind::Directive(..))))
+        .filter(|(_, l)| l.section.as_ref() == Some(name))
+        .map(|(i, _)| i)
+        .last()
+        .unwrap_or(start)
+}
+
+fn split(value: &str) -> Vec<&str> {
+    value.split_whitespace().collect()
+}
+
+impl Document {
+    /// Ensures that an option holding a list, such as `IgnorePkg`, contains a value.
+    ///
+    /// The value is added to the end of the first line that sets the option. If the option
+    /// is not set it is added as with [`set_option`](Document::set_option).
+    ///
+    /// Returns false if the option already contains the value. Options holding a single
+    /// value are already idempotent through `set_option`.
+    pub fn ensure_contains(&mut self, key: &str, value: &str) -> Result<bool, Error> {
+        validate_option(key, Some(value))?;
+        if !keys::is_list(key) || value.is_empty() || value.contains(char::is_whitespace) {
+            return Err(ErrorKind::InvalidValue("options".into(), key.into(), value.into()).into());
+        }
+
+        let lines = self.classify();
+        let current = directives(&lines, "options", key);
+
+        if current.iter().any(|(_, v)| split(v).contains(&value)) {
+            return Ok(false);
+        }
+
+        match current.first() {
+            Some((i, current)) => {
+                let mut values = split(current);
+                values.push(value);
+                self.set_value(*i, key, Some(&values.join(" ")));
+                Ok(true)
+            }
+            None => self.set_option(key, value),
+        }
+    }
+
+    /// Ensures that an option holding a list, such as `IgnorePkg`, does not contain a value.
+    ///
+    /// The value is removed from every line that sets it. Lines left without a value are
+    /// commented out.
+    ///
+    /// Returns false if the option did not contain the value.
+    pub fn ensure_not_contains(&mut self, key: &str, value: &str) -> Result<bool, Error> {
+        validate_option(key, Some(value))?;
+
+        let lines = self.classify();
+        let mut changed = false;
+
+        for (i, current) in directives(&lines, "options", key) {
+            let values = split(&current);
+            if !values.contains(&value) {
+                continue;
+            }
+
+            let values = values
+                .into_iter()
+                .filter(|v| *v != value)
+                .collect::<Vec<_>>();
+            if values.is_empty() {
+                self.comment_line(i);
+            } else {
+                self.set_value(i, key, Some(&values.join(" ")));
+            }
+            changed = true;
+        }
+
+        Ok(changed)
+    }
+
+    /// Ensures that a repository is enabled and matches `repo`.
+    ///
+    /// The repository's SigLevel, Usage and Servers are made to match `repo`. An empty
+    /// SigLevel or Usage means pacman's default is used, so any existing line is commented
+    /// out. Include lines are left alone, so servers from a mirrorlist are kept.
+    ///
+    /// If `after` is given the repository is placed after that repository, moving it if
+    /// needed. A repository that does not exist is uncommented if it was disabled and
+    /// otherwise added, after `after` or at the end of the file.
+    ///
+    /// Only the lines that differ are changed so running this again makes no changes.
+    /// Returns whether anything changed.
+    ///
+    /// ```
+    /// use pacmanconf::{Document, Repository};
+    ///
+    /// # fn main() -> Result<(), pacmanconf::Error> {
+    /// let mut doc: Document = "[options]\n\n[core]\nInclude = /etc/pacman.d/mirrorlist\n\n[extra]\nInclude = /etc/pacman.d/mirrorlist\n".parse()?;
+    ///
+    /// let mut repo = Repository::default();
+    /// repo.name = "ourrepo".into();
+    /// repo.servers = vec!["https://repo.example.com/$arch".into()];
+    /// repo.sig_level = vec!["Optional".into(), "TrustAll".into()];
+    ///
+    /// assert!(doc.ensure_repo(&repo, Some("core"))?);
+    /// assert!(!doc.ensure_repo(&repo, Some("core"))?);
+    /// assert_eq!(doc.to_string(), "[options]\n\n[core]\nInclude = /etc/pacman.d/mirrorlist\n\n[ourrepo]\nSigLevel = Optional TrustAll\nServer = https://repo.example.com/$arch\n\n[extra]\nInclude = /etc/pacman.d/mirrorlist\n");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn ensure_repo(&mut self, repo: &Repository, after: Option<&str>) -> Result<bool, Error> {
+        let name = repo.name.as_str();
+        if !keys::is_repo_name(name) {
+            return Err(ErrorKind::InvalidValue(name.into(), "name".into(), name.into()).into());
+        }
+        SigLevel::parse(name, "SigLevel", &repo.sig_level)?;
+        native::usage(name, &repo.usage)?;
+
+        let directives = [
+            ("SigLevel", &repo.sig_level),
+            ("Usage", &repo.usage),
+            ("Server", &repo.servers),
+        ];
+        for (key, values) in directives {
+            if let Some(value) = values.iter().find(|v| v.contains(['\n', '\r'])) {
+                return Err(ErrorKind::InvalidValue(name.into(), key.into(), value.clone()).into());
+            }
+        }
+
+        let mut changed = self.place_repo(name, after)?;
+        changed |= self.ensure_list(name, "SigLevel", &repo.sig_level);
+        changed |= self.ensure_list(name, "Usage", &repo.usage);
+        changed |= self.ensure_servers(name, &repo.servers);
+        Ok(changed)
+    }
+
+    /// Makes sure the repository's section is enabled and comes after `after`.
+    fn place_repo(&mut self, name: &str, after: Option<&str>) -> Result<bool, Error> {
+        let lines = self.classify();
+        if let Some(after) = after {
+            if find_section(&lines, false, after).is_none() {
+                return Err(ErrorKind::NoRepo(after.into()).into());
+            }
+        }
+
+        let mut changed = false;
+        if find_section(&lines, false, name).is_none() && find_section(&lines, true, name).is_some()
+        {
+            changed = self.enable_repo(name)?;
+        }
+
+        let lines = self.classify();
+        let after = after.and_then(|after| find_section(&lines, false, after));
+
+        let start = match find_section(&lines, false, name) {
+            Some(start) => start,
+            None => {
+                let header = format!("[{}]", name);
+                match after {
+                    Some(after) => {
+                        let at = section_end(&lines, after) + 1;
+                        self.insert_lines(at, vec![String::new(), header]);
+                    }
+                    None => {
+                        if self.lines().last().is_some_and(|l| !l.trim().is_empty()) {
+                            self.insert_line(self.lines().len(), String::new());
+                        }
+                        self.insert_line(self.lines().len(), header);
+                    }
+                }
+                return Ok(true);
+            }
+        };
+
+        let after = match after {
+            Some(after) if start < after => after,
+            _ => return Ok(changed),
+        };
+
+        // take the blank line separating the section from the one before it along with it
+        let end = section_end(&lines, start);
+        let start = match start.checked_sub(1) {
+            Some(blank) if self.lines()[blank].trim().is_empty() => blank,
+            _ => start,
+        };
+        let mut block = self.remove_lines(start..=end);
+        let at = section_end(&lines, after) + 1 - block.len();
+        if !block[0].trim().is_empty() {
+            block.insert(0, String::new());
+        }
+        self.insert_lines(at, block);
+        Ok(true)
+    }
+
+    /// Makes the repository's directive match `values`, joined on a single line.
+    fn ensure_list(&mut self, name: &str, key: &str, values: &[String]) -> bool {
+        let lines = self.classify();
+        let current = directives(&lines, name, key);
+
+        if values.is_empty() {
+            current.iter().for_each(|&(i, _)| self.comment_line(i));
+            return !current.is_empty();
+        }
+
+        let value = values.join(" ");
+        match current.split_first() {
+            Some(((first, v), rest)) => {
+                let differs = split(v) != split(&value);
+                if differs {
+                    self.set_value(*first, key, Some(&value));
+                }
+                rest.iter().for_each(|&(i, _)| self.comment_line(i));
+                differs || !rest.is_empty()
+            }
+            None => {
+                let header = find_section(&lines, false, name).unwrap();
+                self.insert_line(header + 1, format!("{} = {}", key, value));
+                true
+            }
+        }
+    }
+
+    /// Makes the repository's Server lines match `servers`, in order.
+    ///
+    /// Servers that are already in the right order are kept, the rest are commented out
+    /// and any that are missing are inserted next to their neighbours.
+    fn ensure_servers(&mut self, name: &str, servers: &[String]) -> bool {
+        let lines = self.classify();
+        let current = directives(&lines, name, "Server");
+        if current.iter().map(|(_, v)| v).eq(servers) {
+            return false;
+        }
+
+        // keep the servers that appear in the same order as in `servers`
+        let mut kept = Vec::new();
+        let mut next = 0;
+        for (i, value) in &current {
+            match servers[next..].iter().position(|s| s == value) {
+                Some(pos) => {
+                    kept.push((*i, next + pos));
+                    next += pos + 1;
+                }
+                None => self.comment_line(*i),
+            }
+        }
+
+        let header = find_section(&lines, false, name).unwrap();
+        let mut prev = match kept.first() {
+            Some(&(i, _)) => i - 1,
+            None => section_end(&lines, header),
+        };
+        let mut k = 0;
+
+        for (j, server) in servers.iter().enumerate() {
+            match kept.get(k) {
+                Some(&(i, pos)) if pos == j => {
+                    prev = i;
+                    k += 1;
+                }
+                _ => {
+                    prev += 1;
+                    self.insert_line(prev, format!("Server = {}", server));
+                    kept[k..].iter_mut().for_each(|(i, _)| *i += 1);
+                }
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACMAN_CONF: &str = "\
+[options]
+HoldPkg     = pacman glibc
+#IgnorePkg   =
+ParallelDownloads = 5
+
+[core]
+Include = /etc/pacman.d/mirrorlist
+
+[extra]
+Include = /etc/pacman.d/mirrorlist
+
+#[multilib]
+#Include = /etc/pacman.d/mirrorlist
+";
+
+    fn repo(name: &str, servers: &[&str], sig_level: &[&str]) -> Repository {
+        Repository {
+            name: name.into(),
+            servers: servers.iter().map(|s| s.to_string()).collect(),
+            sig_level: sig_level.iter().map(|s| s.to_string()).collect(),
+            ..Repository::default()
+        }
+    }
+
+    #[test]
+    fn ensure_contains() {
+        let mut doc: Document = PACMAN_CONF.parse().unwrap();
+
+        assert!(doc.ensure_contains("IgnorePkg", "linux").unwrap());
+        assert!(doc.ensure_contains("IgnorePkg", "vim").unwrap());
+        assert!(!doc.ensure_contains("IgnorePkg", "linux").unwrap());
+        assert!(!doc.ensure_contains("HoldPkg", "glibc").unwrap());
+        assert!(doc.ensure_not_contains("HoldPkg", "glibc").unwrap());
+        assert!(!doc.ensure_not_contains("HoldPkg", "glibc").unwrap());
+        assert!(doc.ensure_not_contains("IgnorePkg", "linux").unwrap());
+        assert!(doc.ensure_not_contains("IgnorePkg", "vim").unwrap());
+        assert!(doc.ensure_contains("DBPath", "/var").is_err());
+        assert!(doc.ensure_contains("IgnorePkg", "a b").is_err());
+
+        assert!(doc.to_string().starts_with(
+            "[options]\nHoldPkg     = pacman\n#IgnorePkg   = vim\nParallelDownloads = 5\n"
+        ));
+    }
+
+    #[test]
+    fn ensure_repo() {
+        let mut doc: Document = PACMAN_CONF.parse().unwrap();
+        let ourrepo = repo(
+            "ourrepo",
+            &["https://a.example/$arch", "https://b.example/$arch"],
+            &["Required"],
+        );
+
+        assert!(doc.ensure_repo(&ourrepo, Some("extra")).unwrap());
+        assert!(!doc.ensure_repo(&ourrepo, Some("extra")).unwrap());
+        assert!(!doc.ensure_repo(&ourrepo, None).unwrap());
+        assert!(doc.ensure_repo(&repo("multilib", &[], &[]), None).unwrap());
+        assert!(!doc.ensure_repo(&repo("multilib", &[], &[]), None).unwrap());
+
+        let expected = "\
+[extra]
+Include = /etc/pacman.d/mirrorlist
+
+[ourrepo]
+SigLevel = Required
+Server = https://a.example/$arch
+Server = https://b.example/$arch
+
+[multilib]
+Include = /etc/pacman.d/mirrorlist
+";
+        assert!(doc.to_string().ends_with(expected), "{}", doc);
+
+        let ourrepo = repo(
+            "ourrepo",
+            &[
+                "https://c.example/$arch",
+                "https://b.example/$arch",
+                "https://d.example/$arch",
+            ],
+            &[],
+        );
+        assert!(doc.ensure_repo(&ourrepo, Some("multilib")).unwrap());
+        assert!(!doc.ensure_repo(&ourrepo, Some("multilib")).unwrap());
+        assert!(!doc.ensure_repo(&ourrepo, Some("core")).unwrap());
+
+        let expected = "\
+[extra]
+Include = /etc/pacman.d/mirrorlist
+
+[multilib]
+Include = /etc/pacman.d/mirrorlist
+
+[ourrepo]
+#SigLevel = Required
+#Server = https://a.example/$arch
+Server = https://c.example/$arch
+Server = https://b.example/$arch
+Server = https://d.example/$arch
+";
+        assert!(doc.to_string().ends_with(expected), "{}", doc);
+
+        assert!(doc
+            .ensure_repo(&repo("nope", &[], &[]), Some("missing"))
+            .is_err());
+        assert!(doc.ensure_repo(&repo("bad name", &[], &[]), None).is_err());
+        assert!(doc
+            .ensure_repo(&repo("x", &[], &["Sometimes"]), None)
+            .is_err());
+    }
+}
@@ -1,11 +1,16 @@
 use std::error;
 use std::fmt;
 use std::io;
+use std::process::ExitStatus;
 use std::str;
+use std::time::Duration;
 
 /// Error Line holds a line of text and the line number the line is from.
 #[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[non_exhaustive]
 pub struct ErrorLine {
+    /// The file containing the error (if known)
+    pub filename: Option<String>,
     /// The line number that the  error occurred at
     pub number: usize,
     /// The full line containing the error
@@ -18,6 +23,7 @@ impl ErrorLine {
     /// If the line is none then Errors can be created using the the From/Into traits.
     pub fn new<S: Into<String>>(number: usize, line: S) -> ErrorLine {
         ErrorLine {
+            filename: None,
             number,
             line: line.into(),
         }
@@ -26,6 +32,7 @@ impl ErrorLine {
 
 /// A list of possible errors that may occur when parsing a pacman.conf
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum ErrorKind {
     /// A directive was specified outside of a section.
     /// The variant holds the key name.
@@ -39,9 +46,33 @@ pub enum ErrorKind {
     /// A directive was given with an unknown key.
     /// The variant holds the section and key.
     UnknownKey(String, String),
+    /// A repository was specified that does not exist.
+    /// The variant holds the repository name.
+    NoRepo(String),
     /// An error occurred while executing pacman-conf.
     /// This variant hold the stdout of pacman-coonf
+    ///
+    /// This is no longer returned. pacman-conf failing is reported as [`ErrorKind::Exit`].
+    #[deprecated(
+        since = "4.0.0",
+        note = "pacman-conf failing is reported as `ErrorKind::Exit`"
+    )]
     Runtime(String),
+    /// The pacman-conf binary could not be found.
+    /// The variant holds the path that was tried.
+    BinaryNotFound(String),
+    /// pacman-conf did not exit within the timeout.
+    /// The variant holds the timeout.
+    Timeout(Duration),
+    /// pacman-conf exited unsuccessfully with an error that could not be understood.
+    /// The variant holds the exit status and the stderr of pacman-conf.
+    Exit(ExitStatus, String),
+    /// Two repositories would be exported to the same shell variables.
+    /// The variant holds the names of the repositories and the name they share.
+    ShellName(String, String, String),
+    /// A download failed.
+    /// The variant holds the reason.
+    Download(String),
     /// A utf8 error occurred.
     Utf8(str::Utf8Error),
     /// An IO error occurred.
@@ -70,7 +101,20 @@ impl fmt::Display for ErrorKind {
             ErrorKind::InvalidValue(s, k, v) => {
                 write!(fmt, "Invalid value for '{}' in section '{}': '{}'", k, s, v)
             }
+            ErrorKind::NoRepo(r) => write!(fmt, "Repository '{}' does not exist", r),
+            #[allow(deprecated)]
             ErrorKind::Runtime(s) => write!(fmt, "Failed to execute pacman-conf: {}", s),
+            ErrorKind::BinaryNotFound(b) => write!(fmt, "Could not find pacman-conf at '{}'", b),
+            ErrorKind::Timeout(t) => write!(fmt, "pacman-conf timed out after {:?}", t),
+            ErrorKind::Exit(status, s) => {
+                write!(fmt, "pacman-conf failed ({}): {}", status, s.trim_end())
+            }
+            ErrorKind::ShellName(a, b, name) => write!(
+                fmt,
+                "Repositories '{}' and '{}' are both exported as '{}'",
+                a, b, name
+            ),
+            ErrorKind::Download(s) => write!(fmt, "Download failed: {}", s),
             ErrorKind::UnknownKey(s, k) => write!(fmt, "Unknown key: '{}' in section '{}'", s, k),
             ErrorKind::Io(err) => err.fmt(fmt),
             ErrorKind::Utf8(err) => err.fmt(fmt),
@@ -110,6 +154,20 @@ impl From<str::Utf8Error> for Error {
 impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match self.line {
+            Some(ErrorLine {
+                filename: Some(ref filename),
+                number: 0,
+                ..
+            }) => write!(fmt, "{}: {}", filename, self.kind),
+            Some(ErrorLine {
+                filename: Some(ref filename),
+                ref line,
+                number,
+            }) => write!(
+                fmt,
+                "{}: Line {}: {}: {}",
+                filename, number, self.kind, line
+            ),
             Some(ref line) => write!(fmt, "Line {}: {}: {}", line.number, self.kind, line.line),
             None => write!(fmt, "{}", self.kind),
         }
@@ -0,0 +1,251 @@
+use crate::native::{self, SigLevel};
+use crate::sha256;
+use crate::Config;
+
+/// Splits values that hold several words, as pacman does for options that take a list.
+fn words(values: &[String]) -> Vec<String> {
+    values
+        .iter()
+        .flat_map(|v| v.split_whitespace())
+        .map(Into::into)
+        .collect()
+}
+
+/// Sorts a list of values and removes duplicates.
+fn sorted(values: &[String]) -> Vec<String> {
+    let mut values = words(values);
+    values.sort();
+    values.dedup();
+    values
+}
+
+/// Sorts a list of NoUpgrade or NoExtract patterns and removes duplicates.
+///
+/// pacman checks these patterns from last to first and the first that matches decides,
+/// so the order only matters once a pattern is negated with `!`. Then the order is kept
+/// and only the earlier copies of a repeated pattern are removed, as they can never be
+/// reached.
+fn patterns(values: &[String]) -> Vec<String> {
+    let values = &words(values);
+    if values.iter().any(|v| v.starts_with('!')) {
+        let mut kept = Vec::<String>::new();
+        for value in values.iter().rev() {
+            if !kept.contains(value) {
+                kept.push(value.clone());
+            }
+        }
+        kept.reverse();
+        kept
+    } else {
+        sorted(values)
+    }
+}
+
+/// Removes repeated values, keeping the first.
+fn first(values: &[String]) -> Vec<String> {
+    let mut kept = Vec::<String>::new();
+    for value in values {
+        if !kept.contains(value) {
+            kept.push(value.clone());
+        }
+    }
+    kept
+}
+
+/// Resolves everything in a config that depends on the host: `Architecture = auto` and
+/// `$repo` and `$arch` in servers.
+pub(crate) fn resolve(config: &mut Config) {
+    config.architecture = native::architectures(&config.architecture);
+
+    let arch = config.architecture.first().cloned().unwrap_or_default();
+    for repo in &mut config.repos {
+        for server in &mut repo.servers {
+            *server = server.replace("$repo", &repo.name).replace("$arch", &arch);
+        }
+    }
+}
+
+/// Normalises a SigLevel the way pacman-conf does, leaving it as it is if it is invalid.
+fn sig_level(section: &str, values: &[String], or: SigLevel, database: bool) -> Vec<String> {
+    let values = &words(values);
+    match SigLevel::parse(section, "SigLevel", values) {
+        Ok(level) => level.or(or).to_vec(database),
+        Err(_) => values.to_vec(),
+    }
+}
+
+impl Config {
+    /// The config in a normal form, so that two configs that pacman treats the same
+    /// compare equal.
+    ///
+    /// The canonical form is the config as pacman-conf would print it, which is already
+    /// independent of formatting, comments and how the config is split across Included
+    /// files, with the rest of the differences that do not change what pacman does removed:
+    ///
+    /// - Values holding several words, such as `SigLevel = Required TrustedOnly`, are split.
+    /// - IgnorePkg, IgnoreGroup, HoldPkg, NoUpgrade and NoExtract are sorted and duplicates
+    ///   removed, unless a NoUpgrade or NoExtract pattern is negated with `!` and the order
+    ///   matters.
+    /// - Architecture `auto` is resolved, and duplicate CacheDirs, HookDirs and
+    ///   architectures are removed.
+    /// - SigLevel, Usage and CleanMethod are written out in full, and repositories without
+    ///   a SigLevel are given the global one.
+    /// - `$repo` and `$arch` in servers are replaced.
+    /// - Color, ILoveCandy and VerbosePkgLists, which only change pacman's output, are
+    ///   unset.
+    ///
+    /// The order of repositories and of each repository's servers is kept.
+    ///
+    /// ```
+    /// use pacmanconf::Config;
+    ///
+    /// let a: Config = "[options]\nIgnorePkg = b\nIgnorePkg = a\nColor\n[core]\n".parse().unwrap();
+    /// let b: Config = "[options]\nIgnorePkg = a\nIgnorePkg = b\n[core]\nSigLevel = Optional TrustedOnly\n".parse().unwrap();
+    ///
+    /// assert_ne!(a, b);
+    /// assert_eq!(a.canonical(), b.canonical());
+    /// ```
+    pub fn canonical(&self) -> Config {
+        let mut config = self.clone();
+
+        config.hold_pkg = sorted(&config.hold_pkg);
+        config.ignore_pkg = sorted(&config.ignore_pkg);
+        config.ignore_group = sorted(&config.ignore_group);
+        config.no_upgrade = patterns(&config.no_upgrade);
+        config.no_extract = patterns(&config.no_extract);
+
+        resolve(&mut config);
+        config.architecture = first(&config.architecture);
+        config.cache_dir = first(&config.cache_dir);
+        config.hook_dir = first(&config.hook_dir);
+
+        let global = SigLevel::parse("options", "SigLevel", &words(&config.sig_level))
+            .map(|level| level.or(SigLevel::DEFAULT))
+            .unwrap_or(SigLevel::DEFAULT);
+        config.sig_level = sig_level("options", &config.sig_level, SigLevel::DEFAULT, true);
+        config.local_file_sig_level =
+            sig_level("options", &config.local_file_sig_level, global, false);
+        config.remote_file_sig_level =
+            sig_level("options", &config.remote_file_sig_level, global, false);
+        if let Ok(clean_method) = native::clean_method("options", &words(&config.clean_method)) {
+            config.clean_method = clean_method;
+        }
+
+        config.color = false;
+        config.chomp = false;
+        config.verbose_pkg_lists = false;
+
+        for repo in &mut config.repos {
+            repo.sig_level = sig_level(&repo.name, &repo.sig_level, global, true);
+            if let Ok(usage) = native::usage(&repo.name, &words(&repo.usage)) {
+                repo.usage = usage;
+            }
+        }
+
+        config
+    }
+
+    /// A fingerprint of the config that only changes when what pacman does changes.
+    ///
+    /// This is the SHA-256 of the [`canonical`](Config::canonical) config as pacman-conf
+    /// prints it, in lowercase hex, so `sha256sum` of that output gives the same value. It
+    /// does not depend on the platform or the version of this crate beyond changes to the
+    /// canonical form itself.
+    ///
+    /// ```
+    /// use pacmanconf::Config;
+    ///
+    /// let a: Config = "[options]\nIgnorePkg = b\nIgnorePkg = a\n[core]\n".parse().unwrap();
+    /// let b: Config = "[options]\nIgnorePkg = a\nIgnorePkg = b\n[core]\n".parse().unwrap();
+    /// assert_eq!(a.fingerprint(), b.fingerprint());
+    /// assert_eq!(a.fingerprint().len(), 64);
+    /// ```
+    pub fn fingerprint(&self) -> String {
+        sha256::hex_digest(self.canonical().to_string().as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Config {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn patterns() {
+        let values = |s: &str| {
+            s.split_whitespace()
+                .map(Into::into)
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(super::patterns(&values("b a b c")), values("a b c"));
+        assert_eq!(super::patterns(&values("b* !bc b* a")), values("!bc b* a"));
+    }
+
+    #[test]
+    fn canonical() {
+        let a = parse(
+            "[options]\nArchitecture = x86_64\nHoldPkg = pacman\nHoldPkg = glibc\n\
+             CacheDir = /a\nCacheDir = /a\nSigLevel = Required\nColor\n\
+             [core]\nServer = https://a.example/$repo/os/$arch\nUsage = Sync\n\
+             Usage = Search\nUsage = Install\nUsage = Upgrade\n",
+        );
+        let b = parse(
+            "[options]\nArchitecture = x86_64\nHoldPkg = glibc\nHoldPkg = pacman\n\
+             CacheDir = /a\nSigLevel = PackageRequired\nSigLevel = DatabaseRequired\n\
+             [core]\nServer = https://a.example/core/os/x86_64\n\
+             SigLevel = Required TrustedOnly\n",
+        );
+
+        assert_eq!(a.canonical(), b.canonical());
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        assert_eq!(a.canonical().to_string(), b.canonical().to_string());
+
+        let canonical = a.canonical();
+        assert_eq!(canonical.hold_pkg, ["glibc", "pacman"]);
+        assert_eq!(canonical.repos[0].usage, ["All"]);
+        assert_eq!(
+            canonical.local_file_sig_level,
+            ["PackageRequired", "PackageTrustedOnly"]
+        );
+        assert!(!canonical.color);
+        assert_eq!(canonical.canonical(), canonical);
+
+        // ! only negates NoUpgrade and NoExtract patterns, where the order matters
+        let a = parse("[options]\nIgnorePkg = b* !bc\nNoExtract = b* !bc\n");
+        let b = parse("[options]\nIgnorePkg = !bc b*\nNoExtract = !bc b*\n");
+        assert_eq!(a.canonical().ignore_pkg, b.canonical().ignore_pkg);
+        assert_ne!(a.canonical().no_extract, b.canonical().no_extract);
+    }
+
+    #[test]
+    fn fingerprint() {
+        let base = parse(
+            "[options]\nIgnorePkg = linux\n[core]\nServer = https://a.example\n\
+             Server = https://b.example\n[extra]\nServer = https://a.example\n",
+        );
+        let fingerprint = base.fingerprint();
+        assert!(fingerprint
+            .bytes()
+            .all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase()));
+
+        let mut repos = base.clone();
+        repos.repos.reverse();
+        assert_ne!(repos.fingerprint(), fingerprint);
+
+        let mut servers = base.clone();
+        servers.repos[0].servers.reverse();
+        assert_ne!(servers.fingerprint(), fingerprint);
+
+        let mut ignore = base.clone();
+        ignore.ignore_pkg.push("linux-headers".into());
+        assert_ne!(ignore.fingerprint(), fingerprint);
+
+        let mut color = base;
+        color.color = true;
+        assert_eq!(color.fingerprint(), fingerprint);
+    }
+}
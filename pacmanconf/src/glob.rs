@@ -0,0 +1,241 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Matches a string against a shell wildcard pattern.
+///
+/// This follows `fnmatch(3)` with no flags set, which is how pacman matches its patterns:
+/// `*` and `?` also match `/` and leading periods, and `\` escapes the next character.
+pub(crate) fn fnmatch(pattern: &str, string: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let string = string.chars().collect::<Vec<_>>();
+    matches(&pattern, &string)
+}
+
+/// Matches a string against a list of patterns the way pacman does.
+///
+/// Patterns are tried from last to first and the first that matches decides: Some(true)
+/// if it is a plain pattern and Some(false) if it is negated with a leading `!`. A leading
+/// `\` is removed so that a pattern can start with a literal `!`. None if no pattern
+/// matches.
+pub(crate) fn fnmatch_patterns<S: AsRef<str>>(patterns: &[S], string: &str) -> Option<bool> {
+    patterns.iter().rev().find_map(|pattern| {
+        let pattern = pattern.as_ref();
+        let (negated, pattern) = match pattern.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, pattern.strip_prefix('\\').unwrap_or(pattern)),
+        };
+        Some(!negated).filter(|_| fnmatch(pattern, string))
+    })
+}
+
+fn matches(p: &[char], s: &[char]) -> bool {
+    let mut pi = 0;
+    let mut si = 0;
+    let mut star = None;
+
+    while si < s.len() {
+        if let Some(&pc) = p.get(pi) {
+            let step = match pc {
+                '*' => {
+                    star = Some((pi, si));
+                    pi += 1;
+                    continue;
+                }
+                '?' => Some(1),
+                '[' => match bracket(&p[pi..], s[si]) {
+                    Some((true, len)) => Some(len),
+                    Some((false, _)) => None,
+                    None if s[si] == '[' => Some(1),
+                    None => None,
+                },
+                '\\' if pi + 1 < p.len() => Some(2).filter(|_| p[pi + 1] == s[si]),
+                _ => Some(1).filter(|_| pc == s[si]),
+            };
+
+            if let Some(step) = step {
+                pi += step;
+                si += 1;
+                continue;
+            }
+        }
+
+        match star {
+            Some((star_pi, star_si)) => {
+                pi = star_pi + 1;
+                si = star_si + 1;
+                star = Some((star_pi, star_si + 1));
+            }
+            None => return false,
+        }
+    }
+
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// Matches a character against a bracket expression at the start of `p`.
+///
+/// Returns whether the character matched and the length of the expression, or None if
+/// the expression is not terminated, in which case the `[` is taken literally.
+fn bracket(p: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negate = matches!(p.get(1), Some('!') | Some('^'));
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+
+    loop {
+        let ch = *p.get(i)?;
+
+        if ch == ']' && !first {
+            i += 1;
+            break;
+        }
+        first = false;
+
+        if ch == '[' && p.get(i + 1) == Some(&':') {
+            if let Some(len) = p[i + 2..].windows(2).position(|w| w == [':', ']']) {
+                let class = p[i + 2..i + 2 + len].iter().collect::<String>();
+                matched |= class_matches(&class, c);
+                i += len + 4;
+                continue;
+            }
+        }
+
+        let (lo, next) = match ch {
+            '\\' => (*p.get(i + 1)?, i + 2),
+            _ => (ch, i + 1),
+        };
+
+        if p.get(next) == Some(&'-') && p.get(next + 1).is_some_and(|&c| c != ']') {
+            let (hi, after) = match p[next + 1] {
+                '\\' => (*p.get(next + 2)?, next + 3),
+                hi => (hi, next + 2),
+            };
+            matched |= lo <= c && c <= hi;
+            i = after;
+        } else {
+            matched |= lo == c;
+            i = next;
+        }
+    }
+
+    Some((matched != negate, i))
+}
+
+fn class_matches(class: &str, c: char) -> bool {
+    match class {
+        "alnum" => c.is_alphanumeric(),
+        "alpha" => c.is_alphabetic(),
+        "blank" => c == ' ' || c == '\t',
+        "cntrl" => c.is_control(),
+        "digit" => c.is_ascii_digit(),
+        "graph" => !c.is_control() && !c.is_whitespace(),
+        "lower" => c.is_lowercase(),
+        "print" => !c.is_control(),
+        "punct" => c.is_ascii_punctuation(),
+        "space" => c.is_whitespace(),
+        "upper" => c.is_uppercase(),
+        "xdigit" => c.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+fn is_pattern(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// Expands a path containing wildcards into the matching paths, sorted.
+///
+/// Like `glob(3)` with `GLOB_NOCHECK`, the pattern itself is returned if nothing matches.
+/// Wildcards do not match a leading period.
+pub(crate) fn glob(pattern: &str) -> Vec<PathBuf> {
+    if !is_pattern(pattern) {
+        return vec![pattern.into()];
+    }
+
+    let mut paths = vec![PathBuf::new()];
+
+    for component in Path::new(pattern).components() {
+        let component = match component {
+            Component::Normal(c) => c.to_string_lossy(),
+            other => {
+                paths.iter_mut().for_each(|p| p.push(other));
+                continue;
+            }
+        };
+
+        if !is_pattern(&component) {
+            paths.iter_mut().for_each(|p| p.push(&*component));
+            continue;
+        }
+
+        let mut matched = Vec::new();
+        for path in &paths {
+            let dir = if path.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                path.as_path()
+            };
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            let mut names = entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .filter(|name| !name.starts_with('.') || component.starts_with('.'))
+                .filter(|name| fnmatch(&component, name))
+                .collect::<Vec<_>>();
+            names.sort();
+            matched.extend(names.into_iter().map(|name| path.join(name)));
+        }
+        paths = matched;
+    }
+
+    paths.retain(|p| p.exists());
+    if paths.is_empty() {
+        paths.push(pattern.into());
+    }
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fnmatch() {
+        assert!(fnmatch("vim-youcompleteme*", "vim-youcompleteme-git"));
+        assert!(fnmatch("linux", "linux"));
+        assert!(!fnmatch("linux", "linux-lts"));
+        assert!(fnmatch("*", ""));
+        assert!(fnmatch("usr/*", "usr/share/locale"));
+        assert!(fnmatch("?inux", "linux"));
+        assert!(fnmatch("lib[0-9]*", "lib32-glibc"));
+        assert!(!fnmatch("lib[!0-9]*", "lib32-glibc"));
+        assert!(fnmatch("[[:upper:]]*", "Xorg"));
+        assert!(fnmatch("[]]", "]"));
+        assert!(fnmatch("a[b", "a[b"));
+        assert!(fnmatch("\\*", "*"));
+        assert!(!fnmatch("\\*", "a"));
+        assert!(fnmatch("*.conf", ".hidden.conf"));
+        assert!(fnmatch("*a*b*c", "xxaxxbxxbxc"));
+        assert!(!fnmatch("*a*b*c", "xxaxxbxxbx"));
+    }
+
+    #[test]
+    fn test_glob() {
+        assert_eq!(
+            glob("tests/mirror*"),
+            vec![PathBuf::from("tests/mirrorlist")]
+        );
+        assert_eq!(glob("tests/*.nope"), vec![PathBuf::from("tests/*.nope")]);
+        assert_eq!(
+            glob("tests/pacman.conf"),
+            vec![PathBuf::from("tests/pacman.conf")]
+        );
+    }
+}
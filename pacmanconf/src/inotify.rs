@@ -0,0 +1,131 @@
+use std::collections::BTreeSet;
+use std::ffi::CString;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Duration;
+
+/// Watches the directories holding a set of files for changes.
+///
+/// Directories are watched rather than the files themselves so that files replaced by
+/// renaming a new file over them, as editors and [`Document::save`](crate::Document::save)
+/// do, are still noticed.
+#[derive(Debug)]
+pub(crate) struct Inotify {
+    fd: OwnedFd,
+}
+
+impl Inotify {
+    pub(crate) fn new<I, P>(files: I) -> io::Result<Inotify>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let inotify = Inotify {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        };
+
+        let dirs = files
+            .into_iter()
+            .map(|file| match file.as_ref().parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+                _ => Path::new(".").to_path_buf(),
+            })
+            .collect::<BTreeSet<_>>();
+
+        let mask = libc::IN_CLOSE_WRITE
+            | libc::IN_MOVED_TO
+            | libc::IN_MOVED_FROM
+            | libc::IN_CREATE
+            | libc::IN_DELETE
+            | libc::IN_ATTRIB;
+
+        for dir in dirs {
+            let dir = CString::new(dir.as_os_str().as_bytes())?;
+            // a change that can not be watched for would be missed, so give up and let
+            // the caller look at the files itself
+            if unsafe { libc::inotify_add_watch(fd, dir.as_ptr(), mask) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(inotify)
+    }
+
+    /// Reads any pending events, returning whether there were any.
+    pub(crate) fn changed(&self) -> bool {
+        let mut buf = [0u8; 4096];
+        let mut changed = false;
+
+        loop {
+            let n = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+            if n <= 0 {
+                // the buffer is too small for an event, which still means one is pending
+                if n < 0 && io::Error::last_os_error().raw_os_error() == Some(libc::EINVAL) {
+                    changed = true;
+                }
+                return changed;
+            }
+            changed = true;
+        }
+    }
+
+    /// Waits up to `timeout` for an event without reading it.
+    pub(crate) fn wait(&self, timeout: Duration) -> bool {
+        let mut fd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        unsafe { libc::poll(&mut fd, 1, timeout) > 0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn changed() {
+        let dir = env::temp_dir().join(format!("pacmanconf-inotify-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("pacman.conf");
+        fs::write(&file, "[options]\n").unwrap();
+
+        let inotify = Inotify::new([&file]).unwrap();
+        assert!(!inotify.changed());
+        assert!(!inotify.wait(Duration::from_millis(10)));
+
+        fs::write(&file, "[options]\nColor\n").unwrap();
+        assert!(inotify.wait(Duration::from_secs(5)));
+        assert!(inotify.changed());
+        assert!(!inotify.changed());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_dir() {
+        let file = env::temp_dir().join(format!(
+            "pacmanconf-inotify-missing-{}/pacman.conf",
+            process::id()
+        ));
+        assert!(Inotify::new([&file]).is_err());
+    }
+}
@@ -0,0 +1,91 @@
+/// Whether a directive takes a value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Value {
+    /// The directive must be given a value. e.g. `DBPath = /var/lib/pacman/`.
+    Required,
+    /// The directive is a flag and must not be given a value. e.g. `Color`.
+    None,
+    /// The directive may be given with or without a value.
+    Optional,
+}
+
+impl Value {
+    /// Checks if a value (or lack of one) is acceptable for this kind of directive.
+    pub(crate) fn accepts(self, value: Option<&str>) -> bool {
+        match self {
+            Value::Required => value.is_some(),
+            Value::None => value.is_none(),
+            Value::Optional => true,
+        }
+    }
+}
+
+/// Looks up a directive that is valid in the `[options]` section.
+pub(crate) fn option_key(key: &str) -> Option<Value> {
+    let value = match key {
+        "RootDir" | "DBPath" | "CacheDir" | "HookDir" | "GPGDir" | "LogFile" | "HoldPkg"
+        | "IgnorePkg" | "IgnoreGroup" | "Architecture" | "XferCommand" | "NoUpgrade"
+        | "NoExtract" | "CleanMethod" | "SigLevel" | "LocalFileSigLevel" | "RemoteFileSigLevel"
+        | "ParallelDownloads" | "DownloadUser" | "Include" => Value::Required,
+        "UseSyslog"
+        | "Color"
+        | "NoProgressBar"
+        | "TotalDownload"
+        | "CheckSpace"
+        | "VerbosePkgLists"
+        | "DisableDownloadTimeout"
+        | "DisableSandbox"
+        | "DisableSandboxFilesystem"
+        | "DisableSandboxSyscalls"
+        | "ILoveCandy" => Value::None,
+        "UseDelta" => Value::Optional,
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+/// Looks up a directive that is valid in a repository section.
+pub(crate) fn repo_key(key: &str) -> Option<Value> {
+    match key {
+        "Server" | "CacheServer" | "SigLevel" | "Usage" | "Include" => Some(Value::Required),
+        _ => None,
+    }
+}
+
+/// Looks up a directive that is valid in the given section.
+pub(crate) fn directive(section: &str, key: &str) -> Option<Value> {
+    if section == "options" {
+        option_key(key)
+    } else {
+        repo_key(key)
+    }
+}
+
+/// Checks if a section name could be used as a repository.
+pub(crate) fn is_repo_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "options"
+        && name != "local"
+        && !name.contains(|c: char| c.is_whitespace() || c == '[' || c == ']')
+}
+
+/// Checks if a directive's value is split on whitespace into multiple values.
+pub(crate) fn is_list(key: &str) -> bool {
+    matches!(
+        key,
+        "CacheDir"
+            | "HookDir"
+            | "HoldPkg"
+            | "IgnorePkg"
+            | "IgnoreGroup"
+            | "Architecture"
+            | "NoUpgrade"
+            | "NoExtract"
+            | "CleanMethod"
+            | "SigLevel"
+            | "LocalFileSigLevel"
+            | "RemoteFileSigLevel"
+            | "Usage"
+    )
+}
@@ -0,0 +1,533 @@
+use crate::keys;
+use crate::native;
+use crate::Config;
+
+type Directives = Vec<(&'static str, Option<String>)>;
+
+/// How the values a layer sets for a key are combined with the layers before it.
+///
+/// See [`Layers`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MergePolicy {
+    /// The layer's values replace the earlier ones.
+    Override,
+    /// The layer's values are added after the earlier ones, skipping values already set.
+    /// Keys that hold a single value are overridden instead.
+    Append,
+    /// The earlier values are kept and the layer's values are only used if no earlier
+    /// layer set the key.
+    Keep,
+}
+
+/// Where the repositories of a layer go.
+///
+/// See [`Layers::add_at`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Placement {
+    /// Repositories are merged into those of the same name, and new ones are added at
+    /// the end.
+    Merge,
+    /// Repositories are put before all others.
+    Start,
+    /// Repositories are put after all others.
+    End,
+    /// Repositories are put before the named repository, or at the end if there is none.
+    Before(String),
+    /// Repositories are put after the named repository, or at the end if there is none.
+    After(String),
+}
+
+/// A value in a merged config and the layer that set it.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct LayeredValue {
+    /// The section the value is in
+    pub section: String,
+    /// The key of the value
+    pub key: String,
+    /// The value, or None for flags
+    pub value: Option<String>,
+    /// The name of the layer that set the value
+    pub layer: String,
+}
+
+/// A config merged from several layers and where each of its values came from.
+///
+/// See [`Layers::merge`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Merged {
+    /// The merged config
+    pub config: Config,
+    /// Each repository and the name of the layer that placed it
+    pub sections: Vec<(String, String)>,
+    /// Each value in the merged config and the layer that set it, in order
+    pub values: Vec<LayeredValue>,
+}
+
+impl Merged {
+    /// The values of a key in the `[options]` section.
+    pub fn option<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a LayeredValue> + 'a {
+        self.values
+            .iter()
+            .filter(move |v| v.section == "options" && v.key == key)
+    }
+
+    /// The values in a section.
+    pub fn section<'a>(&'a self, section: &'a str) -> impl Iterator<Item = &'a LayeredValue> + 'a {
+        self.values.iter().filter(move |v| v.section == section)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Value {
+    key: &'static str,
+    value: Option<String>,
+    layer: usize,
+}
+
+#[derive(Clone, Debug)]
+struct Repo {
+    name: String,
+    values: Vec<Value>,
+    layer: usize,
+}
+
+/// Whether the values `directives` has for `key` are exactly those in `defaults`.
+fn is_default(defaults: &Directives, directives: &Directives, key: &str) -> bool {
+    let values = |d: &Directives| {
+        d.iter()
+            .filter(|(k, _)| *k == key)
+            .map(|(_, v)| v.clone())
+            .collect::<Vec<_>>()
+    };
+    let default = values(defaults);
+    !default.is_empty() && values(directives) == default
+}
+
+/// Merges several configs, such as a base config and overlays, into one.
+///
+/// Layers are merged in the order they are added, each later layer taking precedence.
+/// A layer only sets the keys that differ from [`Config::default`], so an overlay can be
+/// read from a file holding just the options it changes. As a consequence a layer can
+/// not turn off a flag set by an earlier layer.
+///
+/// Layers read with pacman-conf have every option pacman has a default for filled in,
+/// so a key whose values are exactly pacman's default, such as `DBPath =
+/// /var/lib/pacman/` or a repository's `Usage = All`, does not replace or add to values
+/// an earlier layer set. A layer can therefore not set a key back to pacman's default
+/// either.
+///
+/// How each key is merged is set with [`policy`](Layers::policy) and
+/// [`repo_policy`](Layers::repo_policy). By default CacheDir, HookDir, HoldPkg,
+/// IgnorePkg, IgnoreGroup, NoUpgrade, NoExtract and each repository's Server use
+/// [`MergePolicy::Append`] and every other key, including SigLevel and Architecture,
+/// uses [`MergePolicy::Override`].
+///
+/// ```
+/// use pacmanconf::{Config, Layers, Placement};
+///
+/// let base: Config = "[options]\nIgnorePkg = linux\n[core]\nServer = https://a.example\n".parse().unwrap();
+/// let team: Config = "[options]\nIgnorePkg = mesa\nColor\n[team]\nServer = https://team.example\n".parse().unwrap();
+///
+/// let merged = Layers::new()
+///     .add("base", base)
+///     .add_at("team", team, Placement::Start)
+///     .merge();
+///
+/// assert_eq!(merged.config.ignore_pkg, ["linux", "mesa"]);
+/// assert_eq!(merged.config.repos[0].name, "team");
+/// assert_eq!(merged.option("Color").next().unwrap().layer, "team");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Layers {
+    layers: Vec<(String, Config, Placement)>,
+    policies: Vec<(String, MergePolicy)>,
+    repo_policies: Vec<(String, MergePolicy)>,
+}
+
+impl Layers {
+    /// Creates an empty set of layers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a layer whose repositories are merged by name.
+    pub fn add<S: Into<String>>(&mut self, name: S, config: Config) -> &mut Self {
+        self.add_at(name, config, Placement::Merge)
+    }
+
+    /// Adds a layer whose repositories are placed as given.
+    ///
+    /// With any placement other than [`Placement::Merge`], repositories that an earlier
+    /// layer already has are moved to the new position and replaced rather than merged.
+    pub fn add_at<S: Into<String>>(
+        &mut self,
+        name: S,
+        config: Config,
+        placement: Placement,
+    ) -> &mut Self {
+        self.layers.push((name.into(), config, placement));
+        self
+    }
+
+    /// Sets how a key in the `[options]` section is merged.
+    pub fn policy<S: Into<String>>(&mut self, key: S, policy: MergePolicy) -> &mut Self {
+        self.policies.push((key.into(), policy));
+        self
+    }
+
+    /// Sets how a key in repository sections is merged.
+    pub fn repo_policy<S: Into<String>>(&mut self, key: S, policy: MergePolicy) -> &mut Self {
+        self.repo_policies.push((key.into(), policy));
+        self
+    }
+
+    fn policy_of(&self, repo: bool, key: &str) -> MergePolicy {
+        let policies = if repo {
+            &self.repo_policies
+        } else {
+            &self.policies
+        };
+        if let Some((_, policy)) = policies.iter().rev().find(|(k, _)| k == key) {
+            return *policy;
+        }
+
+        match (repo, key) {
+            (false, "CacheDir")
+            | (false, "HookDir")
+            | (false, "HoldPkg")
+            | (false, "IgnorePkg")
+            | (false, "IgnoreGroup")
+            | (false, "NoUpgrade")
+            | (false, "NoExtract")
+            | (true, "Server") => MergePolicy::Append,
+            _ => MergePolicy::Override,
+        }
+    }
+
+    /// Merges the values a layer sets into those of the earlier layers.
+    ///
+    /// Keys set to exactly their values in `defaults` are left alone once set.
+    fn apply(
+        &self,
+        repo: bool,
+        values: &mut Vec<Value>,
+        directives: Directives,
+        defaults: &Directives,
+        layer: usize,
+    ) {
+        let mut seen = Vec::new();
+        for (key, _) in &directives {
+            if !seen.contains(key) {
+                seen.push(*key);
+            }
+        }
+
+        for key in seen {
+            let new = directives
+                .iter()
+                .filter(|(k, _)| *k == key)
+                .map(|(_, value)| Value {
+                    key,
+                    value: value.clone(),
+                    layer,
+                });
+            let set = values.iter().any(|v| v.key == key);
+            if set && is_default(defaults, &directives, key) {
+                continue;
+            }
+            let list = repo || keys::is_list(key);
+
+            match self.policy_of(repo, key) {
+                MergePolicy::Keep if set => (),
+                MergePolicy::Append if list => {
+                    for value in new {
+                        if !values
+                            .iter()
+                            .any(|v| v.key == key && v.value == value.value)
+                        {
+                            values.push(value);
+                        }
+                    }
+                }
+                _ => {
+                    values.retain(|v| v.key != key);
+                    values.extend(new);
+                }
+            }
+        }
+    }
+
+    /// Merges the layers into a single config.
+    pub fn merge(&self) -> Merged {
+        let unset = Config::default().directives();
+        let defaults = native::defaults().directives();
+        let repo_defaults = vec![("Usage", Some("All".to_string()))];
+        let mut options = Vec::new();
+        let mut repos: Vec<Repo> = Vec::new();
+
+        for (layer, (_, config, placement)) in self.layers.iter().enumerate() {
+            let directives = config
+                .directives()
+                .into_iter()
+                .filter(|d| !unset.contains(d))
+                .collect();
+            self.apply(false, &mut options, directives, &defaults, layer);
+
+            if *placement == Placement::Merge {
+                for repo in &config.repos {
+                    match repos.iter_mut().find(|r| r.name == repo.name) {
+                        Some(existing) => self.apply(
+                            true,
+                            &mut existing.values,
+                            repo.directives(),
+                            &repo_defaults,
+                            layer,
+                        ),
+                        None => {
+                            let mut new = Repo {
+                                name: repo.name.clone(),
+                                values: Vec::new(),
+                                layer,
+                            };
+                            self.apply(
+                                true,
+                                &mut new.values,
+                                repo.directives(),
+                                &repo_defaults,
+                                layer,
+                            );
+                            repos.push(new);
+                        }
+                    }
+                }
+                continue;
+            }
+
+            repos.retain(|r| !config.repos.iter().any(|repo| repo.name == r.name));
+            let position = |name: &str| repos.iter().position(|r| r.name == name);
+            let at = match placement {
+                Placement::Start => 0,
+                Placement::Before(name) => position(name).unwrap_or(repos.len()),
+                Placement::After(name) => position(name).map_or(repos.len(), |i| i + 1),
+                Placement::Merge | Placement::End => repos.len(),
+            };
+            let new = config
+                .repos
+                .iter()
+                .map(|repo| {
+                    let mut new = Repo {
+                        name: repo.name.clone(),
+                        values: Vec::new(),
+                        layer,
+                    };
+                    self.apply(
+                        true,
+                        &mut new.values,
+                        repo.directives(),
+                        &repo_defaults,
+                        layer,
+                    );
+                    new
+                })
+                .collect::<Vec<_>>();
+            repos.splice(at..at, new);
+        }
+
+        let name = |layer: usize| self.layers[layer].0.clone();
+        let mut merged = Merged::default();
+
+        for value in options {
+            // the values come from a valid config so they are valid here too
+            let _ = merged
+                .config
+                .handle_option("options", value.key, value.value.as_deref());
+            merged.values.push(LayeredValue {
+                section: "options".into(),
+                key: value.key.into(),
+                value: value.value,
+                layer: name(value.layer),
+            });
+        }
+        for repo in repos {
+            merged.config.handle_section(&repo.name);
+            merged.sections.push((repo.name.clone(), name(repo.layer)));
+            for value in repo.values {
+                let _ = merged.config.handle_directive(
+                    Some(&repo.name),
+                    value.key,
+                    value.value.as_deref(),
+                );
+                merged.values.push(LayeredValue {
+                    section: repo.name.clone(),
+                    key: value.key.into(),
+                    value: value.value,
+                    layer: name(value.layer),
+                });
+            }
+        }
+
+        merged
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Config {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn merge() {
+        let base = parse(
+            "[options]\nDBPath = /var/lib/pacman/\nCacheDir = /a\nIgnorePkg = linux\n\
+             SigLevel = Required\nParallelDownloads = 5\n\
+             [core]\nServer = https://a.example\n[extra]\nServer = https://a.example\n",
+        );
+        let team = parse(
+            "[options]\nCacheDir = /b\nCacheDir = /a\nIgnorePkg = mesa\nSigLevel = Never\n\
+             DBPath = /team/db/\n[core]\nServer = https://b.example\nSigLevel = Never\n\
+             [team]\nServer = https://team.example\n",
+        );
+
+        let merged = Layers::new().add("base", base).add("team", team).merge();
+        let config = &merged.config;
+        assert_eq!(config.db_path, "/team/db/");
+        assert_eq!(config.cache_dir, ["/a", "/b"]);
+        assert_eq!(config.ignore_pkg, ["linux", "mesa"]);
+        assert_eq!(config.sig_level, ["Never"]);
+        assert_eq!(config.parallel_downloads, 5);
+        assert_eq!(
+            config.repos.iter().map(|r| &r.name).collect::<Vec<_>>(),
+            ["core", "extra", "team"]
+        );
+        assert_eq!(
+            config.repos[0].servers,
+            ["https://a.example", "https://b.example"]
+        );
+        assert_eq!(config.repos[0].sig_level, ["Never"]);
+
+        let layers = |key: &str| {
+            merged
+                .option(key)
+                .map(|v| v.layer.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(layers("CacheDir"), ["base", "team"]);
+        assert_eq!(layers("DBPath"), ["team"]);
+        assert_eq!(layers("ParallelDownloads"), ["base"]);
+        assert_eq!(
+            merged
+                .section("core")
+                .map(|v| v.layer.as_str())
+                .collect::<Vec<_>>(),
+            ["base", "team", "team"]
+        );
+        assert_eq!(merged.sections[2], ("team".into(), "team".into()));
+    }
+
+    #[test]
+    fn pacman_conf_overlay() {
+        let base = parse(
+            "[options]\nDBPath = /base/db/\nCacheDir = /base/cache/\nArchitecture = i686\n\
+             ParallelDownloads = 5\n[core]\nUsage = Sync\nServer = https://a.example\n",
+        );
+        // as pacman-conf prints it, with all of pacman's defaults filled in
+        let overlay = parse(&format!(
+            "{}IgnorePkg = mesa\nColor\n[core]\nUsage = All\nServer = https://b.example\n",
+            native::defaults()
+        ));
+
+        let merged = Layers::new()
+            .add("base", base)
+            .add("overlay", overlay)
+            .merge();
+        let config = &merged.config;
+        assert_eq!(config.db_path, "/base/db/");
+        assert_eq!(config.cache_dir, ["/base/cache/"]);
+        assert_eq!(config.architecture, ["i686"]);
+        assert_eq!(config.parallel_downloads, 5);
+        assert_eq!(config.ignore_pkg, ["mesa"]);
+        assert!(config.color);
+        assert_eq!(config.repos[0].usage, ["Sync"]);
+        assert_eq!(
+            config.repos[0].servers,
+            ["https://a.example", "https://b.example"]
+        );
+
+        // defaults still fill in what no earlier layer set
+        assert_eq!(config.gpg_dir, native::defaults().gpg_dir);
+        assert_eq!(merged.option("GPGDir").next().unwrap().layer, "overlay");
+        assert_eq!(merged.option("DBPath").next().unwrap().layer, "base");
+    }
+
+    #[test]
+    fn policies() {
+        let base = parse(
+            "[options]\nCacheDir = /a\nIgnorePkg = linux\n[core]\nServer = https://a.example\n",
+        );
+        let team = parse("[options]\nCacheDir = /b\nIgnorePkg = mesa\nDBPath = /db/\n[core]\nServer = https://b.example\n");
+
+        let merged = Layers::new()
+            .policy("CacheDir", MergePolicy::Override)
+            .policy("IgnorePkg", MergePolicy::Keep)
+            .policy("DBPath", MergePolicy::Keep)
+            .repo_policy("Server", MergePolicy::Override)
+            .add("base", base)
+            .add("team", team)
+            .merge();
+
+        assert_eq!(merged.config.cache_dir, ["/b"]);
+        assert_eq!(merged.config.ignore_pkg, ["linux"]);
+        assert_eq!(merged.config.db_path, "/db/");
+        assert_eq!(merged.config.repos[0].servers, ["https://b.example"]);
+    }
+
+    #[test]
+    fn placement() {
+        let base = parse("[core]\n[extra]\n[multilib]\n");
+        let names = |merged: Merged| {
+            merged
+                .config
+                .repos
+                .into_iter()
+                .map(|r| r.name)
+                .collect::<Vec<_>>()
+        };
+        let merge = |placement| {
+            let overlay = parse("[testing]\nServer = https://a.example\n[extra]\n");
+            names(
+                Layers::new()
+                    .add("base", base.clone())
+                    .add_at("overlay", overlay, placement)
+                    .merge(),
+            )
+        };
+
+        assert_eq!(
+            merge(Placement::Merge),
+            ["core", "extra", "multilib", "testing"]
+        );
+        assert_eq!(
+            merge(Placement::Start),
+            ["testing", "extra", "core", "multilib"]
+        );
+        assert_eq!(
+            merge(Placement::End),
+            ["core", "multilib", "testing", "extra"]
+        );
+        assert_eq!(
+            merge(Placement::Before("core".into())),
+            ["testing", "extra", "core", "multilib"]
+        );
+        assert_eq!(
+            merge(Placement::After("core".into())),
+            ["core", "testing", "extra", "multilib"]
+        );
+        assert_eq!(
+            merge(Placement::After("nope".into())),
+            ["core", "multilib", "testing", "extra"]
+        );
+    }
+}
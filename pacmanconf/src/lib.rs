@@ -23,10 +23,58 @@
 //! See [`Config`] and [`Options`] on how to use this library.
 
 #![warn(missing_docs)]
+// Error is a public struct so boxing it would be a breaking change.
+#![allow(clippy::result_large_err)]
+#[cfg(feature = "tokio")]
+mod asynchronous;
+mod commented;
+#[cfg(feature = "serde")]
+mod declaration;
+mod diff;
+mod document;
+#[cfg(feature = "download")]
+mod download;
+mod ensure;
 mod error;
+mod fingerprint;
+mod glob;
+#[cfg(target_os = "linux")]
+mod inotify;
+mod keys;
+mod layers;
+mod lock;
+mod matching;
+mod merge;
+mod native;
 mod options;
+mod overrides;
 mod pacmanconf;
+mod paths;
+mod process;
+mod provenance;
+mod session;
+mod sha256;
+mod shared;
+mod shell;
+mod xfer;
 
+pub use crate::commented::*;
+#[cfg(feature = "serde")]
+pub use crate::declaration::*;
+pub use crate::diff::*;
+pub use crate::document::*;
+#[cfg(feature = "download")]
+pub use crate::download::*;
 pub use crate::error::*;
+pub use crate::layers::*;
+pub use crate::lock::*;
+pub use crate::merge::*;
 pub use crate::options::*;
+pub use crate::overrides::*;
 pub use crate::pacmanconf::*;
+pub use crate::paths::*;
+pub use crate::provenance::*;
+pub use crate::session::*;
+pub use crate::shared::*;
+pub use crate::shell::*;
+pub use crate::xfer::*;
@@ -0,0 +1,346 @@
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+use crate::error::{Error, ErrorKind, ErrorLine};
+use crate::fingerprint;
+use crate::sha256;
+use crate::{Config, Diff, Options};
+
+const HEADER: &str = "# pacmanconf lock v1";
+const VERSION: &str = "# pacmanconf lock ";
+const SOURCE: &str = "# Source: ";
+
+/// A file a [`Lock`] was frozen from.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct LockSource {
+    /// The path of the file
+    pub path: String,
+    /// The SHA-256 of the file's contents in lowercase hex, or None if it could not be read
+    pub sha256: Option<String>,
+}
+
+impl LockSource {
+    fn of(path: &str) -> LockSource {
+        LockSource {
+            path: path.into(),
+            sha256: fs::read(path).ok().map(|c| sha256::hex_digest(&c)),
+        }
+    }
+}
+
+/// A config frozen into a single pacman.conf that does not depend on the host.
+///
+/// The config is written out with its Includes inlined, `Architecture = auto` resolved
+/// and `$repo` and `$arch` in servers replaced, so pacman reads it the same way wherever
+/// it is used. A header of comments records the files the config was read from and the
+/// hash of each so that [`verify`](Lock::verify) can tell when the host config has
+/// drifted from the lock.
+///
+/// The lock is written with `Display` and read back with `FromStr`, which only accepts
+/// text starting with the header line of a lock version it understands.
+///
+/// ```no_run
+/// use pacmanconf::{Config, Lock};
+///
+/// # fn main() -> Result<(), pacmanconf::Error> {
+/// let options = Config::options();
+/// let lock = options.freeze()?;
+/// std::fs::write("pacman.lock.conf", lock.to_string())?;
+///
+/// let lock: Lock = std::fs::read_to_string("pacman.lock.conf")?.parse()?;
+/// let drift = lock.verify(&options)?;
+/// if !drift.is_empty() {
+///     print!("{}", drift.diff);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Lock {
+    /// The files the config was read from
+    pub sources: Vec<LockSource>,
+    /// The frozen config
+    pub config: Config,
+}
+
+/// How the host config differs from a [`Lock`].
+///
+/// See [`Lock::verify`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Drift {
+    /// Source files that changed or can no longer be read
+    pub changed_files: Vec<String>,
+    /// Files the config is now read from that it was not frozen from
+    pub added_files: Vec<String>,
+    /// Source files the config is no longer read from, whether or not they changed
+    pub removed_files: Vec<String>,
+    /// How the config differs from the frozen one
+    pub diff: Diff,
+}
+
+impl Drift {
+    /// Returns true if nothing drifted.
+    pub fn is_empty(&self) -> bool {
+        self == &Drift::default()
+    }
+}
+
+/// A copy of a config with everything that depends on the host resolved.
+fn resolved(config: &Config) -> Config {
+    let mut config = config.clone();
+    fingerprint::resolve(&mut config);
+    config
+}
+
+impl Lock {
+    /// Freezes a config read from `files`, hashing the files as they are now.
+    ///
+    /// [`Options::freeze`] reads the config and its files together and should usually be
+    /// used instead.
+    pub fn new<S: AsRef<str>>(config: &Config, files: &[S]) -> Lock {
+        Lock {
+            sources: files.iter().map(|f| LockSource::of(f.as_ref())).collect(),
+            config: resolved(config),
+        }
+    }
+
+    /// Compares the lock against a config and the files it was read from.
+    pub fn drift<S: AsRef<str>>(&self, config: &Config, files: &[S]) -> Drift {
+        let changed_files = self
+            .sources
+            .iter()
+            .filter(|source| LockSource::of(&source.path).sha256 != source.sha256)
+            .map(|source| source.path.clone())
+            .collect();
+        let added_files = files
+            .iter()
+            .map(|f| f.as_ref())
+            .filter(|f| !self.sources.iter().any(|s| s.path == *f))
+            .map(Into::into)
+            .collect();
+        let removed_files = self
+            .sources
+            .iter()
+            .filter(|source| !files.iter().any(|f| f.as_ref() == source.path))
+            .map(|source| source.path.clone())
+            .collect();
+
+        Drift {
+            changed_files,
+            added_files,
+            removed_files,
+            diff: self.config.diff(&resolved(config)),
+        }
+    }
+
+    /// Reads the host config using `options` and compares it against the lock.
+    ///
+    /// A source file can change without the config changing, such as when a comment is
+    /// edited, so [`Drift::diff`] should be looked at to tell if pacman would behave
+    /// differently.
+    pub fn verify(&self, options: &Options) -> Result<Drift, Error> {
+        let provenance = options.provenance()?;
+        let config = options.read()?;
+        Ok(self.drift(&config, provenance.files()))
+    }
+}
+
+impl Options {
+    /// Reads the config and freezes it into a [`Lock`].
+    ///
+    /// The files are found by parsing the config file directly as
+    /// [`provenance`](Options::provenance) does, and hashed before the config is read.
+    pub fn freeze(&self) -> Result<Lock, Error> {
+        let provenance = self.provenance()?;
+        let sources = provenance
+            .files()
+            .iter()
+            .map(|f| LockSource::of(f))
+            .collect();
+        let config = resolved(&self.read()?);
+        Ok(Lock { sources, config })
+    }
+}
+
+impl fmt::Display for Lock {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(fmt, "{}", HEADER)?;
+        writeln!(
+            fmt,
+            "# Frozen by pacmanconf from the files below, do not edit."
+        )?;
+        for source in &self.sources {
+            let sha256 = source.sha256.as_deref().unwrap_or("-");
+            writeln!(fmt, "{}{} {}", SOURCE, sha256, source.path)?;
+        }
+        write!(fmt, "{}", self.config)
+    }
+}
+
+impl FromStr for Lock {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut sources = Vec::new();
+
+        let first = s.lines().next().unwrap_or_default();
+        if first != HEADER {
+            // a lock written by a newer version may mean something else by its contents
+            let kind = match first.strip_prefix(VERSION) {
+                Some(version) => {
+                    ErrorKind::InvalidValue("lock".into(), "Version".into(), version.into())
+                }
+                None => ErrorKind::InvalidValue("lock".into(), "Header".into(), first.into()),
+            };
+            return Err(Error {
+                kind,
+                line: Some(ErrorLine::new(1, first)),
+            });
+        }
+
+        for (n, line) in s.lines().enumerate() {
+            let source = match line.strip_prefix(SOURCE) {
+                Some(source) => source,
+                None => continue,
+            };
+            let (sha256, path) = match source.split_once(' ') {
+                Some((sha256, path)) if sha256 == "-" || sha256.len() == 64 => (sha256, path),
+                _ => {
+                    let kind =
+                        ErrorKind::InvalidValue("lock".into(), "Source".into(), source.into());
+                    return Err(Error {
+                        kind,
+                        line: Some(ErrorLine::new(n + 1, line)),
+                    });
+                }
+            };
+            sources.push(LockSource {
+                path: path.into(),
+                sha256: Some(sha256).filter(|s| *s != "-").map(Into::into),
+            });
+        }
+
+        Ok(Lock {
+            sources,
+            config: s.parse()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn freeze() {
+        let config: Config = "[options]\nArchitecture = auto\nIgnorePkg = linux\n\
+                              [core]\nServer = https://a.example/$repo/os/$arch\n"
+            .parse()
+            .unwrap();
+        let lock = Lock::new(&config, &["/nonexistent/pacman.conf"]);
+
+        assert_eq!(lock.config.architecture, [native::machine()]);
+        assert_eq!(
+            lock.config.repos[0].servers,
+            [format!("https://a.example/core/os/{}", native::machine())]
+        );
+        assert_eq!(lock.sources[0].sha256, None);
+
+        let text = lock.to_string();
+        assert!(text.starts_with(HEADER));
+        assert!(text.contains("# Source: - /nonexistent/pacman.conf\n"));
+        assert_eq!(text.parse::<Lock>().unwrap(), lock);
+
+        let bad = format!("{}\n# Source: abc /etc/pacman.conf\n", HEADER);
+        assert!(bad.parse::<Lock>().is_err());
+    }
+
+    #[test]
+    fn header() {
+        let err = "".parse::<Lock>().unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::InvalidValue(_, k, v) if k == "Header" && v.is_empty())
+        );
+
+        let err = "[options]\n# pacmanconf lock v1\n"
+            .parse::<Lock>()
+            .unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::InvalidValue(_, k, _) if k == "Header"));
+        assert_eq!(err.line.unwrap().number, 1);
+
+        let err = "# pacmanconf lock v2\n[options]\n"
+            .parse::<Lock>()
+            .unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::InvalidValue(_, k, v) if k == "Version" && v == "v2")
+        );
+
+        let lock = format!("{}\n[options]\nColor\n", HEADER)
+            .parse::<Lock>()
+            .unwrap();
+        assert!(lock.config.color);
+    }
+
+    #[test]
+    fn drift() {
+        let dir = env::temp_dir().join(format!("pacmanconf-lock-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let pacman_conf = dir.join("pacman.conf").to_string_lossy().into_owned();
+        let mirrorlist = dir.join("mirrorlist").to_string_lossy().into_owned();
+        fs::write(&pacman_conf, "[core]\n").unwrap();
+
+        let config: Config = "[options]\n[core]\nServer = https://a.example\n"
+            .parse()
+            .unwrap();
+        let lock = Lock::new(&config, &[&pacman_conf]);
+        let lock: Lock = lock.to_string().parse().unwrap();
+        assert!(lock.drift(&config, &[&pacman_conf]).is_empty());
+
+        fs::write(&pacman_conf, "[core]\nInclude = mirrorlist\n").unwrap();
+        let mut moved = config;
+        moved.repos[0].servers.push("https://b.example".into());
+        let drift = lock.drift(&moved, &[&pacman_conf, &mirrorlist]);
+        assert_eq!(drift.changed_files, [pacman_conf.as_str()]);
+        assert_eq!(drift.added_files, [mirrorlist.as_str()]);
+        assert_eq!(drift.diff.repos[0].lists[0].added, ["https://b.example"]);
+        assert!(drift.removed_files.is_empty());
+
+        // an Include dropped from a file that did not itself change
+        let lock = Lock::new(&moved, &[&pacman_conf, &mirrorlist]);
+        let drift = lock.drift(&moved, &[&pacman_conf]);
+        assert!(drift.changed_files.is_empty());
+        assert_eq!(drift.removed_files, [mirrorlist]);
+        assert!(!drift.is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn freeze_in_sysroot() {
+        // reads the config file from under the sysroot given after it
+        let bin = crate::process::fake_pacman_conf("freeze", "cat \"$4$2\"");
+        let sysroot = env::temp_dir().join(format!("pacmanconf-lock-sysroot-{}", process::id()));
+        fs::create_dir_all(sysroot.join("etc")).unwrap();
+        let pacman_conf = sysroot.join("etc/pacman.conf");
+        fs::write(&pacman_conf, "[options]\nArchitecture = x86_64\n[core]\n").unwrap();
+
+        let mut options = Config::options();
+        options
+            .pacman_conf_bin(bin.to_str().unwrap())
+            .sysroot(&sysroot)
+            .pacman_conf("/etc/pacman.conf");
+        let lock = options.freeze().unwrap();
+        assert_eq!(lock.sources[0].path, pacman_conf.to_str().unwrap());
+        assert!(lock.sources[0].sha256.is_some());
+        assert!(lock.verify(&options).unwrap().is_empty());
+
+        fs::remove_dir_all(sysroot).unwrap();
+        fs::remove_file(bin).unwrap();
+    }
+}
@@ -0,0 +1,305 @@
+use crate::glob::{fnmatch, fnmatch_patterns};
+use crate::Config;
+
+/// The patterns of a list option, split into words as pacman does when reading the file.
+pub(crate) fn patterns(values: &[String]) -> Vec<&str> {
+    values.iter().flat_map(|v| v.split_whitespace()).collect()
+}
+
+/// Whether an archive path matches a list of NoUpgrade or NoExtract patterns.
+///
+/// Archive paths have no leading slash, so one is removed from the path and from each
+/// pattern.
+fn matches_path(values: &[String], path: &str) -> bool {
+    let patterns = patterns(values)
+        .into_iter()
+        .map(|pattern| {
+            let split = usize::from(pattern.starts_with(['!', '\\']));
+            let (prefix, pattern) = pattern.split_at(split);
+            format!("{}{}", prefix, pattern.trim_start_matches('/'))
+        })
+        .collect::<Vec<_>>();
+
+    fnmatch_patterns(&patterns, path.trim_start_matches('/')) == Some(true)
+}
+
+impl Config {
+    /// Whether pacman would ignore upgrades to a package, given its name and groups.
+    ///
+    /// This follows libalpm's `alpm_pkg_should_ignore`: the package is ignored if its name
+    /// matches any IgnorePkg pattern or one of its groups matches any IgnoreGroup pattern.
+    /// Patterns are shell wildcards as in `fnmatch(3)`. Unlike NoUpgrade and NoExtract a
+    /// leading `!` does not negate the pattern, so `IgnorePkg = !vim` only matches a
+    /// package named `!vim`.
+    ///
+    /// ```
+    /// use pacmanconf::Config;
+    ///
+    /// let config: Config = "[options]\nIgnorePkg = vim-youcompleteme*\nIgnoreGroup = gnome\n".parse().unwrap();
+    ///
+    /// assert!(config.is_ignored("vim-youcompleteme-git", &[] as &[&str]));
+    /// assert!(config.is_ignored("gedit", &["gnome"]));
+    /// assert!(!config.is_ignored("vim", &["editors"]));
+    /// ```
+    pub fn is_ignored<S: AsRef<str>>(&self, pkgname: &str, groups: &[S]) -> bool {
+        let ignore_group = patterns(&self.ignore_group);
+
+        patterns(&self.ignore_pkg)
+            .iter()
+            .any(|pattern| fnmatch(pattern, pkgname))
+            || groups.iter().any(|group| {
+                ignore_group
+                    .iter()
+                    .any(|pattern| fnmatch(pattern, group.as_ref()))
+            })
+    }
+
+    /// Whether a package is held, so pacman asks before removing it.
+    ///
+    /// HoldPkg patterns are shell wildcards as in `fnmatch(3)`. As with IgnorePkg, a leading
+    /// `!` does not negate the pattern.
+    ///
+    /// ```
+    /// use pacmanconf::Config;
+    ///
+    /// let config: Config = "[options]\nHoldPkg = pacman glibc\n".parse().unwrap();
+    /// assert!(config.is_held("pacman"));
+    /// assert!(!config.is_held("pacman-contrib"));
+    /// ```
+    pub fn is_held(&self, pkgname: &str) -> bool {
+        patterns(&self.hold_pkg)
+            .iter()
+            .any(|pattern| fnmatch(pattern, pkgname))
+    }
+
+    /// Whether pacman would extract a file from a package.
+    ///
+    /// `path` is the path of the file in the package archive, such as
+    /// `usr/share/locale/de/LC_MESSAGES/pacman.mo`. A leading slash is ignored. Directories
+    /// are written with a trailing slash as they are in archives.
+    ///
+    /// Files are not extracted if they match NoExtract, following libalpm's
+    /// `_alpm_fnmatch_patterns`: patterns are shell wildcards where `*` also matches `/`,
+    /// and later patterns take precedence so a pattern starting with `!` excludes paths
+    /// from an earlier one. A leading `\` is dropped, so `\!name` matches a path starting
+    /// with `!`. Files at the top of the archive starting with `.`, such as `.PKGINFO`, are
+    /// package metadata and never extracted into the root.
+    ///
+    /// ```
+    /// use pacmanconf::Config;
+    ///
+    /// let config: Config = "[options]\nNoExtract = usr/share/locale/* !usr/share/locale/en*\n".parse().unwrap();
+    ///
+    /// assert!(!config.should_extract("usr/share/locale/de/LC_MESSAGES/pacman.mo"));
+    /// assert!(config.should_extract("usr/share/locale/en_GB/LC_MESSAGES/pacman.mo"));
+    /// assert!(config.should_extract("/usr/bin/pacman"));
+    /// ```
+    pub fn should_extract(&self, path: &str) -> bool {
+        let path = path.trim_start_matches('/');
+        !path.starts_with('.') && !matches_path(&self.no_extract, path)
+    }
+
+    /// Whether pacman would overwrite a file when upgrading a package.
+    ///
+    /// `path` is the path of the file in the package archive, as for
+    /// [`should_extract`](Config::should_extract), and is matched against NoUpgrade using
+    /// the same rules. When a file that should not be upgraded already exists pacman
+    /// leaves it alone and writes the new version next to it with a `.pacnew` suffix.
+    ///
+    /// ```
+    /// use pacmanconf::Config;
+    ///
+    /// let config: Config = "[options]\nNoUpgrade = etc/pacman.conf\n".parse().unwrap();
+    /// assert!(!config.should_upgrade("etc/pacman.conf"));
+    /// assert!(config.should_upgrade("etc/makepkg.conf"));
+    /// ```
+    pub fn should_upgrade(&self, path: &str) -> bool {
+        !matches_path(&self.no_upgrade, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(ignore_pkg: &[&str], ignore_group: &[&str]) -> Config {
+        Config {
+            ignore_pkg: ignore_pkg.iter().map(|s| s.to_string()).collect(),
+            ignore_group: ignore_group.iter().map(|s| s.to_string()).collect(),
+            ..Config::default()
+        }
+    }
+
+    /// IgnorePkg, IgnoreGroup, the package name, its groups and whether it is ignored.
+    type Case<'a> = (&'a [&'a str], &'a [&'a str], &'a str, &'a [&'a str], bool);
+
+    #[test]
+    fn is_ignored() {
+        let none: &[&str] = &[];
+        let cases: &[Case] = &[
+            // plain names and wildcards
+            (&["foo"], &[], "foo", none, true),
+            (&["foo"], &[], "foobar", none, false),
+            (&["foo*"], &[], "foobar", none, true),
+            (&["f?o"], &[], "foo", none, true),
+            (&["[a-f]oo"], &[], "goo", none, false),
+            (&[], &[], "foo", none, false),
+            // any pattern matching ignores the package, a leading ! is not special
+            (&["foo*", "!foobar"], &[], "foobar", none, true),
+            (&["!foobar", "foo*"], &[], "foobar", none, true),
+            (&["!foo"], &[], "foo", none, false),
+            (&["!foo"], &[], "!foo", none, true),
+            // a backslash escapes the next character
+            (&["\\!foo"], &[], "!foo", none, true),
+            (&["\\foo"], &[], "foo", none, true),
+            // groups
+            (&[], &["grp"], "foo", &["grp"], true),
+            (&[], &["grp"], "foo", &["other"], false),
+            (&[], &["g*", "!gnome"], "foo", &["gnome"], true),
+            (&[], &["!gnome"], "foo", &["gnome", "gtk"], false),
+            // IgnoreGroup is not matched against the package name
+            (&[], &["foo"], "foo", none, false),
+            // values holding several patterns are split
+            (&["bar foo* !foobar"], &[], "foobar", none, true),
+            (&["bar baz"], &[], "baz", none, true),
+        ];
+
+        for (ignore_pkg, ignore_group, pkgname, groups, ignored) in cases {
+            let config = config(ignore_pkg, ignore_group);
+            assert_eq!(
+                config.is_ignored(pkgname, groups),
+                *ignored,
+                "IgnorePkg = {:?}, IgnoreGroup = {:?}, {} in {:?}",
+                ignore_pkg,
+                ignore_group,
+                pkgname,
+                groups
+            );
+        }
+    }
+
+    #[test]
+    fn is_held() {
+        let config = Config {
+            hold_pkg: vec!["pacman".into(), "glibc lib32-*".into(), "!linux".into()],
+            ..Config::default()
+        };
+
+        assert!(config.is_held("pacman"));
+        assert!(config.is_held("glibc"));
+        assert!(config.is_held("lib32-glibc"));
+        assert!(!config.is_held("linux"));
+        assert!(config.is_held("!linux"));
+        assert!(!config.is_held("pacman-contrib"));
+    }
+
+    #[test]
+    fn should_extract() {
+        let cases: &[(&[&str], &str, bool)] = &[
+            (&[], "usr/bin/pacman", true),
+            (&["usr/bin/pacman"], "usr/bin/pacman", false),
+            (&["usr/bin/pacman"], "/usr/bin/pacman", false),
+            (&["/usr/bin/pacman"], "usr/bin/pacman", false),
+            (&["usr/bin/pacman"], "usr/bin/pacman-key", true),
+            // * matches across directories
+            (&["usr/share/doc/*"], "usr/share/doc/pacman/README", false),
+            (&["usr/share/*.txt"], "usr/share/doc/a.txt", false),
+            (&["usr/share/doc/*"], "usr/share/doc/", false),
+            (&["usr/share/doc/*"], "usr/share/doc", true),
+            // the last pattern to match decides
+            (
+                &["usr/share/locale/*", "!usr/share/locale/en*"],
+                "usr/share/locale/de/LC_MESSAGES/a.mo",
+                false,
+            ),
+            (
+                &["usr/share/locale/*", "!usr/share/locale/en*"],
+                "usr/share/locale/en_GB/LC_MESSAGES/a.mo",
+                true,
+            ),
+            (
+                &["usr/share/locale/*", "!usr/share/locale/en*"],
+                "usr/share/locale/en/",
+                true,
+            ),
+            (
+                &["usr/share/locale/*", "!usr/share/locale/en*"],
+                "usr/share/locale/",
+                false,
+            ),
+            (
+                &["!usr/share/locale/en*", "usr/share/locale/*"],
+                "usr/share/locale/en_GB/a.mo",
+                false,
+            ),
+            (
+                &["usr/share/locale/*", "!/usr/share/locale/en*"],
+                "usr/share/locale/en/a.mo",
+                true,
+            ),
+            (
+                &[
+                    "usr/share/locale/*",
+                    "!usr/share/locale/en*",
+                    "usr/share/locale/en_GB/*",
+                ],
+                "usr/share/locale/en_GB/a.mo",
+                false,
+            ),
+            (
+                &[
+                    "usr/share/locale/*",
+                    "!usr/share/locale/en*",
+                    "usr/share/locale/en_GB/*",
+                ],
+                "usr/share/locale/en_US/a.mo",
+                true,
+            ),
+            // escaping and classes
+            (&["\\!weird"], "!weird", false),
+            (&["usr/lib/lib[0-9]*.so"], "usr/lib/lib32.so", false),
+            (&["usr/lib/lib[!0-9]*.so"], "usr/lib/lib32.so", true),
+            (
+                &["usr/share/man/man?/*"],
+                "usr/share/man/man1/pacman.8.gz",
+                false,
+            ),
+            // package metadata
+            (&[], ".PKGINFO", false),
+            (&[], ".MTREE", false),
+            (&[], "usr/lib/.keep", true),
+        ];
+
+        for (no_extract, path, extract) in cases {
+            let config = Config {
+                no_extract: no_extract.iter().map(|s| s.to_string()).collect(),
+                ..Config::default()
+            };
+            assert_eq!(
+                config.should_extract(path),
+                *extract,
+                "NoExtract = {:?}, {}",
+                no_extract,
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn should_upgrade() {
+        let config = Config {
+            no_upgrade: vec![
+                "etc/pacman.conf /etc/pacman.d/*".into(),
+                "!etc/pacman.d/gnupg/*".into(),
+            ],
+            ..Config::default()
+        };
+
+        assert!(!config.should_upgrade("etc/pacman.conf"));
+        assert!(!config.should_upgrade("/etc/pacman.conf"));
+        assert!(!config.should_upgrade("etc/pacman.d/mirrorlist"));
+        assert!(config.should_upgrade("etc/pacman.d/gnupg/gpg.conf"));
+        assert!(config.should_upgrade("etc/makepkg.conf"));
+        assert!(config.should_upgrade(".PKGINFO"));
+    }
+}
@@ -0,0 +1,444 @@
+use crate::document::LineKind;
+use crate::Document;
+
+/// The state of every occurrence of a key in a section: whether each one is commented out
+/// and its value.
+type State = Vec<(bool, Option<String>)>;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Key {
+    state: State,
+    lines: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Item {
+    Text(String),
+    Directive(String, String),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Section {
+    name: Option<String>,
+    header: Option<String>,
+    active: bool,
+    items: Vec<Item>,
+    keys: Vec<(String, Key)>,
+}
+
+impl Section {
+    fn key(&self, key: &str) -> Option<&Key> {
+        self.keys.iter().find(|(k, _)| k == key).map(|(_, k)| k)
+    }
+
+    fn lines(&self) -> Vec<String> {
+        let items = self.items.iter().map(|item| match item {
+            Item::Text(line) | Item::Directive(_, line) => line.clone(),
+        });
+        self.header.iter().cloned().chain(items).collect()
+    }
+
+    /// The meaning of the section, ignoring comments and formatting.
+    fn state(&self) -> (bool, Vec<(&String, &State)>) {
+        let mut keys = self
+            .keys
+            .iter()
+            .map(|(k, key)| (k, &key.state))
+            .collect::<Vec<_>>();
+        keys.sort();
+        (self.active, keys)
+    }
+}
+
+fn sections(doc: &Document) -> Vec<Section> {
+    let mut sections = vec![Section {
+        active: true,
+        ..Default::default()
+    }];
+    let mut current = 0;
+
+    for (line, raw) in doc.classify().into_iter().zip(doc.lines()) {
+        match line.kind {
+            Some(LineKind::Section(name)) => {
+                match sections.iter().position(|s| s.name.as_ref() == Some(&name)) {
+                    Some(pos) => {
+                        current = pos;
+                        sections[pos].items.push(Item::Text(raw.clone()));
+                    }
+                    None => {
+                        current = sections.len();
+                        sections.push(Section {
+                            name: Some(name),
+                            header: Some(raw.clone()),
+                            active: !line.commented,
+                            ..Default::default()
+                        });
+                    }
+                }
+            }
+            Some(LineKind::Directive(key, value)) => {
+                let section = &mut sections[current];
+                section
+                    .items
+                    .push(Item::Directive(key.clone(), raw.clone()));
+                let pos = match section.keys.iter().position(|(k, _)| *k == key) {
+                    Some(pos) => pos,
+                    None => {
+                        section.keys.push((key, Key::default()));
+                        section.keys.len() - 1
+                    }
+                };
+                let entry = &mut section.keys[pos].1;
+                entry.state.push((line.commented, value));
+                entry.lines.push(raw.clone());
+            }
+            None => sections[current].items.push(Item::Text(raw.clone())),
+        }
+    }
+
+    sections
+}
+
+fn key_of<'a>(section: Option<&'a Section>, key: &str) -> Option<&'a Key> {
+    section.and_then(|s| s.key(key))
+}
+
+fn find<'a>(sections: &'a [Section], name: &Option<String>) -> Option<&'a Section> {
+    sections.iter().find(|s| s.name == *name)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Pick {
+    Local,
+    New,
+    Conflict,
+}
+
+fn pick<T: PartialEq>(base: &T, local: &T, new: &T) -> Pick {
+    if local == new || local == base {
+        Pick::New
+    } else if new == base {
+        Pick::Local
+    } else {
+        Pick::Conflict
+    }
+}
+
+/// A change made both locally and upstream that could not be merged.
+///
+/// The local version is kept in the merged document.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Conflict {
+    /// The section of the conflict. None for lines before the first section.
+    pub section: Option<String>,
+    /// The key of the conflicting directive. None if the section as a whole conflicts.
+    pub key: Option<String>,
+    /// The lines in the base file
+    pub base: Vec<String>,
+    /// The lines in the local file
+    pub local: Vec<String>,
+    /// The lines in the pacnew file
+    pub pacnew: Vec<String>,
+}
+
+/// The result of a three-way merge.
+///
+/// See [`Document::merge`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Merge {
+    /// The merged document
+    pub document: Document,
+    /// The changes that could not be merged
+    pub conflicts: Vec<Conflict>,
+}
+
+impl Document {
+    /// Performs a three-way merge of a pacman.conf and its pacnew.
+    ///
+    /// `base` is the original config that `local` was edited from and `pacnew` is the new
+    /// upstream version of `base`. Usually `base` is the pacman.conf shipped by the previously
+    /// installed version of pacman.
+    ///
+    /// Sections and directives are merged by meaning rather than by line. A directive that was
+    /// changed locally, including being commented or uncommented, keeps its local state
+    /// unless upstream also changed it, in which case a [`Conflict`] is reported and the local
+    /// version is kept. Everything else, including comments, ordering and new defaults, is
+    /// taken from `pacnew`. Sections that were only added locally are kept at their local
+    /// position.
+    ///
+    /// ```
+    /// use pacmanconf::Document;
+    ///
+    /// let base: Document = "[options]\n#Color\n\n#[multilib]\n#Include = /etc/pacman.d/mirrorlist\n".parse().unwrap();
+    /// let local: Document = "[options]\nColor\n\n[multilib]\nInclude = /etc/pacman.d/mirrorlist\n".parse().unwrap();
+    /// let pacnew: Document = "[options]\n#Color\nParallelDownloads = 5\n\n#[multilib]\n#Include = /etc/pacman.d/mirrorlist\n".parse().unwrap();
+    ///
+    /// let merge = Document::merge(&base, &local, &pacnew);
+    /// assert!(merge.conflicts.is_empty());
+    /// assert_eq!(merge.document.to_string(), "[options]\nColor\nParallelDownloads = 5\n\n[multilib]\nInclude = /etc/pacman.d/mirrorlist\n");
+    /// ```
+    pub fn merge(base: &Document, local: &Document, pacnew: &Document) -> Merge {
+        let base = sections(base);
+        let local_sections = sections(local);
+        let new = sections(pacnew);
+        let mut conflicts = Vec::new();
+        let mut out: Vec<(Option<String>, Vec<String>)> = Vec::new();
+
+        for ns in &new {
+            let bs = find(&base, &ns.name);
+            let ls = find(&local_sections, &ns.name);
+            let header = |s: Option<&Section>| s.map(|s| s.active);
+
+            let header_pick = pick(&header(bs), &header(ls), &Some(ns.active));
+            if header_pick == Pick::Conflict {
+                conflicts.push(Conflict {
+                    section: ns.name.clone(),
+                    key: None,
+                    base: bs.map(Section::lines).unwrap_or_default(),
+                    local: ls.map(Section::lines).unwrap_or_default(),
+                    pacnew: ns.lines(),
+                });
+            }
+
+            let mut lines = Vec::new();
+            match (header_pick, ls) {
+                (Pick::New, _) => lines.extend(ns.header.clone()),
+                (_, Some(ls)) => lines.extend(ls.header.clone()),
+                (_, None) => continue,
+            }
+
+            let mut last = lines.len();
+            let mut done = Vec::new();
+            let mut merge_key = |key: &str, lines: &mut Vec<String>| {
+                let (b, l, n) = (key_of(bs, key), key_of(ls, key), key_of(Some(ns), key));
+                let state = |k: Option<&Key>| k.map(|k| k.state.clone());
+                let lines_of = |k: Option<&Key>| k.map(|k| k.lines.clone()).unwrap_or_default();
+
+                match pick(&state(b), &state(l), &state(n)) {
+                    Pick::New => lines.extend(lines_of(n)),
+                    Pick::Local => lines.extend(lines_of(l)),
+                    Pick::Conflict => {
+                        conflicts.push(Conflict {
+                            section: ns.name.clone(),
+                            key: Some(key.into()),
+                            base: lines_of(b),
+                            local: lines_of(l),
+                            pacnew: lines_of(n),
+                        });
+                        lines.extend(lines_of(l));
+                    }
+                }
+            };
+
+            for item in &ns.items {
+                match item {
+                    Item::Text(line) => lines.push(line.clone()),
+                    Item::Directive(key, _) if done.contains(key) => (),
+                    Item::Directive(key, _) => {
+                        merge_key(key, &mut lines);
+                        done.push(key.clone());
+                        last = lines.len();
+                    }
+                }
+            }
+
+            let extra = ls
+                .iter()
+                .flat_map(|ls| &ls.keys)
+                .map(|(key, _)| key)
+                .filter(|key| ns.key(key).is_none());
+            let mut extra_lines = Vec::new();
+            for key in extra {
+                merge_key(key, &mut extra_lines);
+            }
+            lines.splice(last..last, extra_lines);
+
+            out.push((ns.name.clone(), lines));
+        }
+
+        for (i, ls) in local_sections.iter().enumerate() {
+            if find(&new, &ls.name).is_some() {
+                continue;
+            }
+
+            match find(&base, &ls.name) {
+                Some(bs) if bs.state() == ls.state() => continue,
+                Some(bs) => conflicts.push(Conflict {
+                    section: ls.name.clone(),
+                    key: None,
+                    base: bs.lines(),
+                    local: ls.lines(),
+                    pacnew: Vec::new(),
+                }),
+                None => (),
+            }
+
+            let position = |s: &Section| out.iter().position(|(name, _)| *name == s.name);
+            let pos = local_sections[..i]
+                .iter()
+                .rev()
+                .find_map(position)
+                .map(|pos| pos + 1)
+                .or_else(|| local_sections[i + 1..].iter().find_map(position))
+                .unwrap_or(out.len());
+
+            // keep sections separated when inserting after one that ends the file
+            if let Some((_, prev)) = pos.checked_sub(1).and_then(|prev| out.get_mut(prev)) {
+                if prev.last().is_some_and(|line| !line.trim().is_empty()) {
+                    prev.push(String::new());
+                }
+            }
+            out.insert(pos, (ls.name.clone(), ls.lines()));
+        }
+
+        let mut text = out
+            .into_iter()
+            .flat_map(|(_, lines)| lines)
+            .collect::<Vec<_>>()
+            .join("\n");
+        if pacnew.to_string().ends_with('\n') {
+            text.push('\n');
+        }
+
+        Merge {
+            document: Document::parse(local.filename(), &text),
+            conflicts,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "\
+# /etc/pacman.conf
+[options]
+HoldPkg     = pacman glibc
+#Color
+#IgnorePkg   =
+CheckSpace
+
+[core]
+Include = /etc/pacman.d/mirrorlist
+
+[community]
+Include = /etc/pacman.d/mirrorlist
+
+#[multilib]
+#Include = /etc/pacman.d/mirrorlist
+";
+
+    const LOCAL: &str = "\
+# /etc/pacman.conf
+[options]
+HoldPkg     = pacman glibc linux
+Color
+IgnorePkg   = linux
+CheckSpace
+ILoveCandy
+
+[core]
+Include = /etc/pacman.d/mirrorlist
+
+[community]
+Include = /etc/pacman.d/mirrorlist
+
+[multilib]
+Include = /etc/pacman.d/mirrorlist
+
+[custom]
+Server = file:///home/custompkgs
+";
+
+    const PACNEW: &str = "\
+# /etc/pacman.conf
+[options]
+HoldPkg     = pacman glibc systemd
+#Color
+#IgnorePkg   =
+CheckSpace
+# Download several packages at once
+ParallelDownloads = 5
+
+#[core-testing]
+#Include = /etc/pacman.d/mirrorlist
+
+[core]
+Include = /etc/pacman.d/mirrorlist
+
+#[multilib]
+#Include = /etc/pacman.d/mirrorlist
+";
+
+    #[test]
+    fn merge() {
+        let base = Document::parse(None, BASE);
+        let local = Document::parse(Some("pacman.conf"), LOCAL);
+        let pacnew = Document::parse(None, PACNEW);
+
+        let merge = Document::merge(&base, &local, &pacnew);
+
+        assert_eq!(
+            merge.document.to_string(),
+            "\
+# /etc/pacman.conf
+[options]
+HoldPkg     = pacman glibc linux
+Color
+IgnorePkg   = linux
+CheckSpace
+# Download several packages at once
+ParallelDownloads = 5
+ILoveCandy
+
+#[core-testing]
+#Include = /etc/pacman.d/mirrorlist
+
+[core]
+Include = /etc/pacman.d/mirrorlist
+
+[multilib]
+Include = /etc/pacman.d/mirrorlist
+
+[custom]
+Server = file:///home/custompkgs
+"
+        );
+        assert_eq!(merge.document.filename(), Some("pacman.conf"));
+
+        assert_eq!(
+            merge.conflicts,
+            vec![Conflict {
+                section: Some("options".into()),
+                key: Some("HoldPkg".into()),
+                base: vec!["HoldPkg     = pacman glibc".into()],
+                local: vec!["HoldPkg     = pacman glibc linux".into()],
+                pacnew: vec!["HoldPkg     = pacman glibc systemd".into()],
+            }]
+        );
+    }
+
+    #[test]
+    fn removed_upstream() {
+        let base = Document::parse(None, "[options]\n[community]\nInclude = mirrorlist\n");
+        let local = Document::parse(
+            None,
+            "[options]\n[community]\nServer = https://example.com\n",
+        );
+        let pacnew = Document::parse(None, "[options]\n");
+
+        let merge = Document::merge(&base, &local, &pacnew);
+        assert_eq!(
+            merge.document.to_string(),
+            "[options]\n\n[community]\nServer = https://example.com\n"
+        );
+        assert_eq!(merge.conflicts.len(), 1);
+        assert_eq!(merge.conflicts[0].section.as_deref(), Some("community"));
+        assert_eq!(merge.conflicts[0].key, None);
+
+        let merge = Document::merge(&base, &base, &pacnew);
+        assert_eq!(merge.document.to_string(), pacnew.to_string());
+        assert!(merge.conflicts.is_empty());
+    }
+}
@@ -0,0 +1,430 @@
+use std::env::consts::ARCH;
+#[cfg(unix)]
+use std::ffi::CStr;
+
+use crate::error::{Error, ErrorKind, ErrorLine};
+use crate::keys;
+use crate::{Config, Provenance, Source};
+
+/// The architecture `Architecture = auto` means, which pacman takes from `uname`.
+///
+/// This is the machine the library runs on rather than the one it was built for, so a
+/// 32-bit build on a 64-bit kernel gets the same answer as pacman.
+pub(crate) fn machine() -> String {
+    #[cfg(unix)]
+    {
+        let mut uts = unsafe { std::mem::zeroed::<libc::utsname>() };
+        if unsafe { libc::uname(&mut uts) } == 0 {
+            let machine = unsafe { CStr::from_ptr(uts.machine.as_ptr()) };
+            return machine.to_string_lossy().into_owned();
+        }
+    }
+    ARCH.into()
+}
+
+/// Splits a list of architectures into words and resolves `auto` in place.
+pub(crate) fn architectures<S: AsRef<str>>(values: &[S]) -> Vec<String> {
+    values
+        .iter()
+        .flat_map(|v| v.as_ref().split_whitespace())
+        .map(|arch| match arch {
+            "auto" => machine(),
+            _ => arch.into(),
+        })
+        .collect()
+}
+
+// pacman's compiled in defaults
+const ROOT_DIR: &str = "/";
+pub(crate) const DB_PATH: &str = "/var/lib/pacman/";
+const CACHE_DIR: &str = "/var/cache/pacman/pkg/";
+const HOOK_DIR: &str = "/etc/pacman.d/hooks/";
+const GPG_DIR: &str = "/etc/pacman.d/gnupg/";
+pub(crate) const LOG_FILE: &str = "/var/log/pacman.log";
+
+/// A signature checking level, see `SigLevel` in pacman.conf (5).
+///
+/// Each field is None when it is inherited from the global SigLevel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct SigLevel {
+    package: Option<&'static str>,
+    package_trust: Option<&'static str>,
+    database: Option<&'static str>,
+    database_trust: Option<&'static str>,
+}
+
+impl SigLevel {
+    pub(crate) const DEFAULT: SigLevel = SigLevel {
+        package: Some("Optional"),
+        package_trust: Some("TrustedOnly"),
+        database: Some("Optional"),
+        database_trust: Some("TrustedOnly"),
+    };
+
+    pub(crate) fn parse(
+        section: &str,
+        key: &str,
+        values: &[String],
+    ) -> Result<SigLevel, ErrorKind> {
+        let mut level = SigLevel::default();
+
+        for value in values {
+            let (package, database, word) = if let Some(word) = value.strip_prefix("Package") {
+                (true, false, word)
+            } else if let Some(word) = value.strip_prefix("Database") {
+                (false, true, word)
+            } else {
+                (true, true, value.as_str())
+            };
+
+            let (check, trust) = match word {
+                "Never" => (Some("Never"), None),
+                "Optional" => (Some("Optional"), None),
+                "Required" => (Some("Required"), None),
+                "TrustedOnly" => (None, Some("TrustedOnly")),
+                "TrustAll" => (None, Some("TrustAll")),
+                _ => {
+                    return Err(ErrorKind::InvalidValue(
+                        section.into(),
+                        key.into(),
+                        value.clone(),
+                    ))
+                }
+            };
+
+            if package {
+                level.package = check.or(level.package);
+                level.package_trust = trust.or(level.package_trust);
+            }
+            if database {
+                level.database = check.or(level.database);
+                level.database_trust = trust.or(level.database_trust);
+            }
+        }
+
+        Ok(level)
+    }
+
+    pub(crate) fn or(self, other: SigLevel) -> SigLevel {
+        SigLevel {
+            package: self.package.or(other.package),
+            package_trust: self.package_trust.or(other.package_trust),
+            database: self.database.or(other.database),
+            database_trust: self.database_trust.or(other.database_trust),
+        }
+    }
+
+    /// Formats the level the way pacman-conf prints it.
+    pub(crate) fn to_vec(self, database: bool) -> Vec<String> {
+        let mut values = Vec::new();
+        let mut push = |prefix, check: Option<&str>, trust: Option<&str>| match check {
+            Some("Never") => values.push(format!("{}Never", prefix)),
+            check => {
+                values.push(format!("{}{}", prefix, check.unwrap_or("Optional")));
+                values.push(format!("{}{}", prefix, trust.unwrap_or("TrustedOnly")));
+            }
+        };
+
+        push("Package", self.package, self.package_trust);
+        if database {
+            push("Database", self.database, self.database_trust);
+        }
+        values
+    }
+}
+
+/// Orders the values that are set like pacman-conf, which prints flags rather than the
+/// values as they were written.
+fn flags(
+    section: &str,
+    key: &str,
+    values: &[String],
+    all: &[&str],
+) -> Result<Vec<String>, ErrorKind> {
+    if let Some(value) = values.iter().find(|v| !all.contains(&v.as_str())) {
+        return Err(ErrorKind::InvalidValue(
+            section.into(),
+            key.into(),
+            value.clone(),
+        ));
+    }
+
+    Ok(all
+        .iter()
+        .filter(|flag| values.iter().any(|v| v == *flag))
+        .map(|flag| flag.to_string())
+        .collect())
+}
+
+pub(crate) fn usage(section: &str, values: &[String]) -> Result<Vec<String>, ErrorKind> {
+    let all = ["Sync", "Search", "Install", "Upgrade"];
+    let mut usage = flags(
+        section,
+        "Usage",
+        values,
+        &["All", "Sync", "Search", "Install", "Upgrade"],
+    )?;
+
+    if usage.is_empty() || usage[0] == "All" || usage.len() == all.len() {
+        usage = vec!["All".into()];
+    }
+    Ok(usage)
+}
+
+fn error(source: &Source, kind: ErrorKind) -> Error {
+    let line = match &source.value {
+        Some(value) => format!("{} = {}", source.key, value),
+        None => source.key.clone(),
+    };
+
+    Error {
+        kind,
+        line: Some(ErrorLine {
+            filename: Some(source.origin.filename.clone()),
+            ..ErrorLine::new(source.origin.line_number, line)
+        }),
+    }
+}
+
+pub(crate) fn clean_method(section: &str, values: &[String]) -> Result<Vec<String>, ErrorKind> {
+    let mut clean_method = flags(
+        section,
+        "CleanMethod",
+        values,
+        &["KeepInstalled", "KeepCurrent"],
+    )?;
+    if clean_method.is_empty() {
+        clean_method.push("KeepInstalled".into());
+    }
+    Ok(clean_method)
+}
+
+/// Attributes an error to the last directive that set a key.
+fn error_at(provenance: &Provenance, section: &str, key: &str, kind: ErrorKind) -> Error {
+    match provenance.section(section).filter(|s| s.key == key).last() {
+        Some(source) => error(source, kind),
+        None => kind.into(),
+    }
+}
+
+/// Prefixes one of pacman's default paths with the root directory.
+pub(crate) fn rooted(root: &str, path: &str) -> String {
+    format!(
+        "{}/{}",
+        root.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    )
+}
+
+/// Reads a config file and the files it Includes without using pacman-conf.
+///
+/// The result matches what pacman-conf would print: list values are split, `$repo` and
+/// `$arch` are expanded and anything left unset is filled in with pacman's defaults.
+/// `arch` replaces the config's architecture as pacman's `--arch` does.
+pub(crate) fn read(
+    path: &str,
+    root_dir: Option<&str>,
+    arch: Option<&str>,
+) -> Result<Config, Error> {
+    expand(&Provenance::from_file(path)?, root_dir, arch)
+}
+
+/// The config pacman-conf prints for an empty config file.
+pub(crate) fn defaults() -> Config {
+    expand(&Provenance::default(), None, None).unwrap_or_default()
+}
+
+fn expand(
+    provenance: &Provenance,
+    root_dir: Option<&str>,
+    arch: Option<&str>,
+) -> Result<Config, Error> {
+    let mut config = Config::default();
+    let mut parallel_downloads = false;
+
+    for source in provenance.section("options").filter(|s| s.key != "Include") {
+        let values = match source.value.as_deref() {
+            Some(value) if keys::is_list(&source.key) => {
+                value.split_whitespace().map(Some).collect()
+            }
+            value => vec![value],
+        };
+
+        for value in values {
+            config
+                .handle_option("options", &source.key, value)
+                .map_err(|kind| error(source, kind))?;
+        }
+
+        if source.key == "ParallelDownloads" {
+            if config.parallel_downloads == 0 {
+                let value = source.value.clone().unwrap_or_default();
+                let kind = ErrorKind::InvalidValue("options".into(), source.key.clone(), value);
+                return Err(error(source, kind));
+            }
+            parallel_downloads = true;
+        }
+    }
+
+    let sig_level = |key: &str, values: &[String]| {
+        SigLevel::parse("options", key, values)
+            .map_err(|kind| error_at(provenance, "options", key, kind))
+    };
+    let global = sig_level("SigLevel", &config.sig_level)?.or(SigLevel::DEFAULT);
+    let local = sig_level("LocalFileSigLevel", &config.local_file_sig_level)?.or(global);
+    let remote = sig_level("RemoteFileSigLevel", &config.remote_file_sig_level)?.or(global);
+    config.sig_level = global.to_vec(true);
+    config.local_file_sig_level = local.to_vec(false);
+    config.remote_file_sig_level = remote.to_vec(false);
+
+    config.clean_method = clean_method("options", &config.clean_method)
+        .map_err(|kind| error_at(provenance, "options", "CleanMethod", kind))?;
+
+    let rooted_dirs = root_dir.is_some() || !config.root_dir.is_empty();
+    if let Some(root_dir) = root_dir {
+        config.root_dir = root_dir.into();
+    }
+    if config.root_dir.is_empty() {
+        config.root_dir = ROOT_DIR.into();
+    }
+    if config.db_path.is_empty() {
+        config.db_path = match rooted_dirs {
+            true => rooted(&config.root_dir, DB_PATH),
+            false => DB_PATH.into(),
+        };
+    }
+    if config.log_file.is_empty() {
+        config.log_file = match rooted_dirs {
+            true => rooted(&config.root_dir, LOG_FILE),
+            false => LOG_FILE.into(),
+        };
+    }
+    if config.gpg_dir.is_empty() {
+        config.gpg_dir = GPG_DIR.into();
+    }
+    if config.cache_dir.is_empty() {
+        config.cache_dir.push(CACHE_DIR.into());
+    }
+    if config.hook_dir.is_empty() {
+        config.hook_dir.push(HOOK_DIR.into());
+    }
+    if !parallel_downloads {
+        config.parallel_downloads = 1;
+    }
+
+    config.architecture = match arch {
+        Some(arch) => architectures(&[arch]),
+        None => architectures(&config.architecture),
+    };
+    if config.architecture.is_empty() {
+        config.architecture.push(machine());
+    }
+    let arch = config.architecture[0].clone();
+
+    let names = provenance.sections().iter().map(|(name, _)| name.as_str());
+
+    for name in names.filter(|name| *name != "options") {
+        if config.repos.iter().any(|repo| repo.name == name) {
+            continue;
+        }
+        config.handle_section(name);
+
+        for source in provenance.section(name) {
+            if source.key == "Include" {
+                continue;
+            }
+
+            let values = match source.value.as_deref() {
+                Some(value) if keys::is_list(&source.key) => {
+                    value.split_whitespace().map(Some).collect()
+                }
+                value => vec![value],
+            };
+            for value in values {
+                config
+                    .handle_directive(Some(name), &source.key, value)
+                    .map_err(|kind| error(source, kind))?;
+            }
+        }
+
+        let repo = config.repos.last_mut().unwrap();
+        for server in &mut repo.servers {
+            *server = server.replace("$repo", name).replace("$arch", &arch);
+        }
+        repo.usage =
+            usage(name, &repo.usage).map_err(|kind| error_at(provenance, name, "Usage", kind))?;
+        if !repo.sig_level.is_empty() {
+            repo.sig_level = SigLevel::parse(name, "SigLevel", &repo.sig_level)
+                .map_err(|kind| error_at(provenance, name, "SigLevel", kind))?
+                .or(global)
+                .to_vec(true);
+        }
+    }
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sig_level() {
+        let parse = |s: &str| {
+            let values = s.split_whitespace().map(Into::into).collect::<Vec<_>>();
+            SigLevel::parse("options", "SigLevel", &values)
+        };
+
+        assert_eq!(
+            parse("Required DatabaseOptional")
+                .unwrap()
+                .or(SigLevel::DEFAULT)
+                .to_vec(true),
+            [
+                "PackageRequired",
+                "PackageTrustedOnly",
+                "DatabaseOptional",
+                "DatabaseTrustedOnly"
+            ]
+        );
+        assert_eq!(
+            parse("PackageNever TrustAll")
+                .unwrap()
+                .or(SigLevel::DEFAULT)
+                .to_vec(true),
+            ["PackageNever", "DatabaseOptional", "DatabaseTrustAll"]
+        );
+        assert_eq!(
+            parse("").unwrap().or(SigLevel::DEFAULT).to_vec(false),
+            ["PackageOptional", "PackageTrustedOnly"]
+        );
+        assert!(parse("Sometimes").is_err());
+    }
+
+    #[test]
+    fn defaults() {
+        let config = read("/dev/null", Some("/chroot"), None).unwrap();
+
+        assert_eq!(config.root_dir, "/chroot");
+        assert_eq!(config.db_path, "/chroot/var/lib/pacman/");
+        assert_eq!(config.log_file, "/chroot/var/log/pacman.log");
+        assert_eq!(config.cache_dir, [CACHE_DIR]);
+        assert_eq!(config.architecture, [machine()]);
+        assert_eq!(config.clean_method, ["KeepInstalled"]);
+        assert_eq!(config.parallel_downloads, 1);
+        assert!(config.repos.is_empty());
+
+        let config = read("/dev/null", None, None).unwrap();
+        assert_eq!(config.db_path, DB_PATH);
+    }
+
+    #[test]
+    fn auto_architecture() {
+        let ini = "[options]\nArchitecture = auto i686\n[core]\nServer = /$arch\n";
+        let provenance = Provenance::trace("pacman.conf", |_| Ok(ini.into())).unwrap();
+        let config = expand(&provenance, None, None).unwrap();
+
+        assert_eq!(config.architecture, [machine(), "i686".into()]);
+        assert_eq!(config.repos[0].servers, [format!("/{}", machine())]);
+    }
+}
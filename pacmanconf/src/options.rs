@@ -1,11 +1,25 @@
-use crate::{pacmanconf, Config, Error};
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use crate::{native, pacmanconf, process, Config, Error, Overrides, Paths, Provenance};
 
 /// The options struct allows you to change settings prior to building.
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct Options {
     conf_binrary: Option<String>,
-    pacman_conf: Option<String>,
-    root_dir: Option<String>,
+    pacman_conf: Option<OsString>,
+    root_dir: Option<OsString>,
+    sysroot: Option<OsString>,
+    arch: Option<String>,
+    verbose: bool,
+    env_clear: bool,
+    env: Vec<(OsString, Option<OsString>)>,
+    current_dir: Option<PathBuf>,
+    timeout: Option<Duration>,
 }
 
 impl Config {
@@ -31,32 +45,399 @@ impl Options {
 
     /// Configure the path for the pacman config file. Not setting this
     /// will cause the system default to be used.
-    pub fn pacman_conf<S: Into<String>>(&mut self, s: S) -> &mut Self {
-        self.pacman_conf = Some(s.into());
+    pub fn pacman_conf<S: AsRef<OsStr>>(&mut self, s: S) -> &mut Self {
+        self.pacman_conf = Some(s.as_ref().into());
         self
     }
 
     /// Configures pacman's  root directory/
-    pub fn root_dir<S: Into<String>>(&mut self, s: S) -> &mut Self {
-        self.root_dir = Some(s.into());
+    pub fn root_dir<S: AsRef<OsStr>>(&mut self, s: S) -> &mut Self {
+        self.root_dir = Some(s.as_ref().into());
+        self
+    }
+
+    /// Configures the directory pacman-conf changes root into before reading the config.
+    pub fn sysroot<S: AsRef<OsStr>>(&mut self, s: S) -> &mut Self {
+        self.sysroot = Some(s.as_ref().into());
+        self
+    }
+
+    /// Configures the architecture used instead of the config file's `Architecture`, as
+    /// pacman's `--arch` does. `auto` is resolved to the architecture of this machine.
+    ///
+    /// pacman-conf has no flag for this, so the architecture is replaced once the config
+    /// has been read and servers are expanded again from the `$arch` in the config file.
+    /// The output of [`expand`](Options::expand) is left as it is.
+    pub fn arch<S: Into<String>>(&mut self, s: S) -> &mut Self {
+        self.arch = Some(s.into());
+        self
+    }
+
+    /// Passes `--verbose` to pacman-conf.
+    pub fn verbose(&mut self, verbose: bool) -> &mut Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Sets an environment variable for pacman-conf.
+    pub fn env<K: Into<OsString>, V: Into<OsString>>(&mut self, key: K, val: V) -> &mut Self {
+        self.env.push((key.into(), Some(val.into())));
+        self
+    }
+
+    /// Removes an environment variable for pacman-conf.
+    pub fn env_remove<K: Into<OsString>>(&mut self, key: K) -> &mut Self {
+        self.env.push((key.into(), None));
+        self
+    }
+
+    /// Clears the environment of pacman-conf so only variables set with
+    /// [`env`](Options::env) are passed to it.
+    pub fn env_clear(&mut self) -> &mut Self {
+        self.env_clear = true;
+        self.env.clear();
         self
     }
 
+    /// Configures the working directory of pacman-conf.
+    ///
+    /// Relative paths, such as the config file or an `Include`, are resolved against
+    /// this directory.
+    pub fn current_dir<P: Into<PathBuf>>(&mut self, dir: P) -> &mut Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// Configures how long pacman-conf may run before it is killed. By default there is
+    /// no limit.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Builds the pacman-conf command these options run.
+    pub fn command(&self) -> Command {
+        let mut cmd = process::command(
+            self.conf_binrary.as_deref().map(OsStr::new),
+            self.pacman_conf.as_deref(),
+            self.root_dir.as_deref(),
+        );
+
+        if let Some(sysroot) = &self.sysroot {
+            cmd.arg("--sysroot").arg(sysroot);
+        }
+        if self.verbose {
+            cmd.arg("--verbose");
+        }
+        if self.env_clear {
+            cmd.env_clear();
+        }
+        for (key, val) in &self.env {
+            match val {
+                Some(val) => cmd.env(key, val),
+                None => cmd.env_remove(key),
+            };
+        }
+        if let Some(dir) = &self.current_dir {
+            cmd.current_dir(dir);
+        }
+
+        cmd
+    }
+
     /// Read the config file into a config instance.
     pub fn read(&self) -> Result<Config, Error> {
-        pacmanconf::Config::with_opts(
-            self.conf_binrary.as_ref(),
-            self.pacman_conf.as_ref(),
-            self.root_dir.as_ref(),
-        )
+        self.finish(self.expand()?.parse()?, None)
+    }
+
+    /// Applies the options pacman-conf has no flag for to a config it has read.
+    ///
+    /// `contents` are the config file contents given to [`read_str`](Options::read_str).
+    pub(crate) fn finish(
+        &self,
+        mut config: Config,
+        contents: Option<&str>,
+    ) -> Result<Config, Error> {
+        let arch = match &self.arch {
+            Some(arch) => arch,
+            None => return Ok(config),
+        };
+        Overrides::new()
+            .architecture(arch.as_str())
+            .apply(&mut config);
+
+        // pacman-conf has already expanded $arch with the config file's architecture
+        let provenance = match contents {
+            Some(contents) => {
+                let sysroot = self.sysroot.as_deref().map(utf8).transpose()?;
+                Provenance::from_contents(sysroot, contents)?
+            }
+            None => self.provenance()?,
+        };
+        let arch = config.architecture.first().cloned().unwrap_or_default();
+        for repo in &mut config.repos {
+            let servers = provenance
+                .section(&repo.name)
+                .filter(|s| s.key == "Server")
+                .filter_map(|s| s.value.as_deref())
+                .collect::<Vec<_>>();
+            if servers.len() == repo.servers.len() {
+                repo.servers = servers
+                    .iter()
+                    .map(|s| s.replace("$repo", &repo.name).replace("$arch", &arch))
+                    .collect();
+            }
+        }
+        Ok(config)
+    }
+
+    /// Read the config file into a config instance without using pacman-conf.
+    ///
+    /// The config file and its Includes are parsed directly and anything left unset is
+    /// filled in with pacman's usual defaults, so this works on systems without pacman.
+    /// The result should match [`read`](Options::read) but pacman-conf remains the
+    /// authority on how pacman reads its config.
+    ///
+    /// Only the config file and root directory are used. The options that only affect
+    /// how pacman-conf is run are ignored. Both must be UTF-8.
+    pub fn read_native(&self) -> Result<Config, Error> {
+        let root_dir = self.root_dir.as_deref().map(utf8).transpose()?;
+        native::read(self.config_path()?, root_dir, self.arch.as_deref())
+    }
+
+    /// The path of the config file as a `str`, for the parts of the crate that read the
+    /// file themselves.
+    fn config_path(&self) -> Result<&str, Error> {
+        match &self.pacman_conf {
+            Some(path) => utf8(path),
+            None => Ok(pacmanconf::PACMAN_CONF),
+        }
+    }
+
+    /// Read the paths of the config file using pacman-conf.
+    ///
+    /// Unlike [`read`](Options::read) this works when the paths are not UTF-8, including
+    /// the config file and root directory given to these options.
+    pub fn read_paths(&self) -> Result<Paths, Error> {
+        let stdout = process::run(&mut self.command(), self.timeout)?;
+        Ok(Paths::from_bytes(&stdout))
     }
 
     /// Expand and dump the config file into a string.
     pub fn expand(&self) -> Result<String, Error> {
-        pacmanconf::Config::expand_with_opts(
-            self.conf_binrary.as_ref(),
-            self.pacman_conf.as_ref(),
-            self.root_dir.as_ref(),
+        let stdout = process::run(&mut self.command(), self.timeout)?;
+        process::expanded(stdout)
+    }
+
+    /// Read config file contents held in memory into a config instance.
+    ///
+    /// See [`expand_str`](Options::expand_str).
+    pub fn read_str(&self, contents: &str) -> Result<Config, Error> {
+        self.finish(self.expand_str(contents)?.parse()?, Some(contents))
+    }
+
+    /// Expand and dump config file contents held in memory into a string.
+    ///
+    /// The contents are written to a private temporary file that pacman-conf reads in
+    /// place of the config file, and which is removed afterwards. The file is created in
+    /// the temporary directory, or in `/tmp` under the [`sysroot`](Options::sysroot) if one
+    /// is set. Relative `Include` paths are resolved against the
+    /// [`current_dir`](Options::current_dir).
+    ///
+    /// Includes are followed as usual so contents from an untrusted source can read any
+    /// file pacman-conf has access to.
+    pub fn expand_str(&self, contents: &str) -> Result<String, Error> {
+        let file = process::TempFile::new(&self.temp_dir(), contents.as_bytes())?;
+        let stdout = process::run(&mut self.temp_command(&file), self.timeout)?;
+        process::expanded(stdout)
+    }
+
+    /// The directory a config held in memory is written to.
+    pub(crate) fn temp_dir(&self) -> PathBuf {
+        match &self.sysroot {
+            Some(sysroot) => Path::new(sysroot).join("tmp"),
+            None => env::temp_dir(),
+        }
+    }
+
+    /// The pacman-conf command that reads a config written to a temporary file.
+    pub(crate) fn temp_command(&self, file: &process::TempFile) -> Command {
+        // pacman-conf opens the config from inside the sysroot
+        let path = match &self.sysroot {
+            Some(_) => Path::new("/tmp").join(file.path().file_name().unwrap()),
+            None => file.path().to_path_buf(),
+        };
+
+        let mut options = self.clone();
+        options.pacman_conf = None;
+        let mut cmd = options.command();
+        cmd.arg("--config").arg(path);
+        cmd
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(crate) fn get_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Read where each value in the config file was set.
+    ///
+    /// This parses the config file and its Includes directly instead of using pacman-conf.
+    /// See [`Provenance`] for more information.
+    ///
+    /// With a [`sysroot`](Options::sysroot) the files are read from under it, and the
+    /// paths recorded are the ones they have on this system. The sysroot must be UTF-8.
+    pub fn provenance(&self) -> Result<Provenance, Error> {
+        match &self.sysroot {
+            Some(sysroot) => Provenance::from_sysroot(utf8(sysroot)?, self.config_path()?),
+            None => Provenance::from_file(self.config_path()?),
+        }
+    }
+}
+
+fn utf8(path: &OsStr) -> Result<&str, Error> {
+    path.to_str().ok_or_else(|| {
+        let msg = format!("path is not valid UTF-8: {}", path.to_string_lossy());
+        io::Error::new(io::ErrorKind::InvalidInput, msg).into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command() {
+        let mut options = Config::options();
+        options
+            .pacman_conf_bin("/usr/bin/pacman-conf")
+            .pacman_conf("tests/pacman.conf")
+            .root_dir("/mnt")
+            .sysroot("/image")
+            .arch("aarch64")
+            .verbose(true)
+            .env("LANG", "C")
+            .env_remove("PATH")
+            .current_dir("/tmp");
+        let cmd = options.command();
+
+        assert_eq!(cmd.get_program(), "/usr/bin/pacman-conf");
+        assert_eq!(
+            cmd.get_args().collect::<Vec<_>>(),
+            [
+                "--root",
+                "/mnt",
+                "--config",
+                "tests/pacman.conf",
+                "--sysroot",
+                "/image",
+                "--verbose"
+            ]
+        );
+        assert_eq!(
+            cmd.get_envs().collect::<Vec<_>>(),
+            [
+                ("LANG".as_ref(), Some("C".as_ref())),
+                ("PATH".as_ref(), None)
+            ]
+        );
+        assert_eq!(cmd.get_current_dir(), Some("/tmp".as_ref()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_paths() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = OsStr::from_bytes(b"/etc/pacman\xff.conf");
+        let mut options = Config::options();
+        options.pacman_conf(path).root_dir(path);
+
+        let args = options
+            .command()
+            .get_args()
+            .map(|a| a.to_os_string())
+            .collect::<Vec<_>>();
+        let expected = [OsStr::new("--root"), path, OsStr::new("--config"), path];
+        assert_eq!(args, expected);
+        assert!(options.read_native().is_err());
+        assert!(options.provenance().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn expand_str() {
+        // prints the config file it is given and its path
+        let bin = process::fake_pacman_conf("expand-str", "cat \"$2\"\necho \"$2\"");
+
+        let mut options = Config::options();
+        options
+            .pacman_conf_bin(bin.to_str().unwrap())
+            .pacman_conf("/etc/pacman.conf");
+        let output = options.expand_str("[options]\nColor\n").unwrap();
+        let (contents, path) = output.rsplit_once('\n').unwrap();
+        assert_eq!(contents, "[options]\nColor");
+        assert!(Path::new(path).starts_with(env::temp_dir()));
+        assert!(!Path::new(path).exists());
+
+        std::fs::remove_file(bin).unwrap();
+    }
+
+    #[test]
+    fn provenance_in_sysroot() {
+        let sysroot = env::temp_dir().join(format!("pacmanconf-sysroot-{}", std::process::id()));
+        std::fs::create_dir_all(sysroot.join("etc/pacman.d")).unwrap();
+        std::fs::write(
+            sysroot.join("etc/pacman.conf"),
+            "[options]\nInclude = /etc/pacman.d/*.conf\n",
         )
+        .unwrap();
+        std::fs::write(sysroot.join("etc/pacman.d/repos.conf"), "[core]\n").unwrap();
+
+        let mut options = Config::options();
+        options.sysroot(&sysroot).pacman_conf("/etc/pacman.conf");
+        let provenance = options.provenance().unwrap();
+        let files = [
+            sysroot.join("etc/pacman.conf"),
+            sysroot.join("etc/pacman.d/repos.conf"),
+        ];
+        assert_eq!(provenance.files(), files.map(|f| f.display().to_string()));
+        assert!(provenance.find_repo("core").is_some());
+
+        std::fs::remove_dir_all(sysroot).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn arch() {
+        // expands the servers for the config file's architecture as pacman-conf does
+        let bin = process::fake_pacman_conf("arch", "sed 's/$repo/core/; s/$arch/x86_64/' \"$2\"");
+        let ini = "[options]\nArchitecture = x86_64\n\
+                   [core]\nServer = https://a.example/$repo/os/$arch\n";
+
+        let mut options = Config::options();
+        options
+            .pacman_conf_bin(bin.to_str().unwrap())
+            .arch("aarch64");
+        let config = options.read_str(ini).unwrap();
+        assert_eq!(config.architecture, ["aarch64"]);
+        assert_eq!(
+            config.repos[0].servers,
+            ["https://a.example/core/os/aarch64"]
+        );
+
+        let path = env::temp_dir().join(format!("pacmanconf-arch-{}.conf", std::process::id()));
+        std::fs::write(&path, ini).unwrap();
+        options.pacman_conf(&path);
+        for config in [options.read().unwrap(), options.read_native().unwrap()] {
+            assert_eq!(config.architecture, ["aarch64"]);
+            assert_eq!(
+                config.repos[0].servers,
+                ["https://a.example/core/os/aarch64"]
+            );
+        }
+        std::fs::remove_file(path).unwrap();
+
+        std::fs::remove_file(bin).unwrap();
     }
 }
@@ -0,0 +1,318 @@
+use std::io::{stdout, IsTerminal};
+
+use crate::error::{Error, ErrorKind};
+use crate::native::{self, rooted, DB_PATH, LOG_FILE};
+use crate::Config;
+
+const SECTION: &str = "command line";
+
+/// The value of pacman's `--color` flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Never use color
+    Never,
+    /// Use color when stdout is a terminal
+    Auto,
+    /// Always use color
+    Always,
+}
+
+impl ColorMode {
+    /// Parses the value of `--color` the way pacman does.
+    pub fn parse(s: &str) -> Option<ColorMode> {
+        match s {
+            "never" => Some(ColorMode::Never),
+            "auto" => Some(ColorMode::Auto),
+            "always" => Some(ColorMode::Always),
+            _ => None,
+        }
+    }
+}
+
+/// Command line flags that pacman lets override the config file.
+///
+/// Each flag is applied with pacman's own rules:
+///
+/// - `--cachedir` and `--arch` replace the list from the config file. Giving them more
+///   than once builds up a new list.
+/// - `--hookdir` adds to the hook directories from the config file.
+/// - `--dbpath`, `--gpgdir` and `--logfile` replace the config file's value.
+/// - `--color` decides whether color is used, ignoring `Color` in the config file.
+/// - `--root` replaces the root directory and moves the database and log file under the
+///   new root unless they were set some other way.
+///
+/// ```
+/// use pacmanconf::{Config, Overrides};
+///
+/// # fn main() -> Result<(), pacmanconf::Error> {
+/// let overrides = Overrides::from_args(&["-Syu", "--root", "/mnt", "--cachedir=/tmp/pkg"])?;
+///
+/// let mut config = Config::default();
+/// config.db_path = "/var/lib/pacman/".into();
+/// overrides.apply(&mut config);
+///
+/// assert_eq!(config.root_dir, "/mnt");
+/// assert_eq!(config.db_path, "/mnt/var/lib/pacman/");
+/// assert_eq!(config.cache_dir, ["/tmp/pkg"]);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Overrides {
+    cache_dir: Vec<String>,
+    hook_dir: Vec<String>,
+    architecture: Vec<String>,
+    db_path: Option<String>,
+    gpg_dir: Option<String>,
+    log_file: Option<String>,
+    root_dir: Option<String>,
+    color: Option<ColorMode>,
+}
+
+impl Overrides {
+    /// Creates an empty Overrides instance that leaves the config unchanged.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Picks out the flags that override the config file from pacman's arguments.
+    ///
+    /// Other arguments are ignored, as is anything after `--`. Long options must be
+    /// spelled out in full, pacman's abbreviations of them are not recognised.
+    pub fn from_args<I, S>(args: I) -> Result<Overrides, Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut overrides = Overrides::new();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let arg = arg.as_ref();
+            let mut value = |flag: &str, inline: Option<&str>| match inline {
+                Some(value) => Ok(value.to_string()),
+                None => args.next().map(|v| v.as_ref().to_string()).ok_or_else(|| {
+                    Error::from(ErrorKind::MissingValue(SECTION.into(), flag.into()))
+                }),
+            };
+
+            if arg == "--" {
+                break;
+            } else if let Some(long) = arg.strip_prefix("--") {
+                let (flag, inline) = match long.split_once('=') {
+                    Some((flag, value)) => (flag, Some(value)),
+                    None => (long, None),
+                };
+
+                match flag {
+                    "cachedir" => overrides.cache_dir(value(arg, inline)?),
+                    "hookdir" => overrides.hook_dir(value(arg, inline)?),
+                    "arch" => overrides.architecture(value(arg, inline)?),
+                    "dbpath" => overrides.db_path(value(arg, inline)?),
+                    "gpgdir" => overrides.gpg_dir(value(arg, inline)?),
+                    "logfile" => overrides.log_file(value(arg, inline)?),
+                    "root" => overrides.root_dir(value(arg, inline)?),
+                    "color" => {
+                        let mode = value(arg, inline)?;
+                        let color = ColorMode::parse(&mode).ok_or_else(|| {
+                            ErrorKind::InvalidValue(SECTION.into(), "--color".into(), mode)
+                        })?;
+                        overrides.color(color)
+                    }
+                    _ => &mut overrides,
+                };
+            } else if let Some(short) = arg.strip_prefix('-').filter(|s| !s.is_empty()) {
+                // -b and -r take a value, either the rest of the group or the next argument
+                if let Some(i) = short.find(['b', 'r']) {
+                    let inline = Some(&short[i + 1..]).filter(|s| !s.is_empty());
+                    let flag = format!("-{}", &short[i..=i]);
+                    match &short[i..=i] {
+                        "b" => overrides.db_path(value(&flag, inline)?),
+                        _ => overrides.root_dir(value(&flag, inline)?),
+                    };
+                }
+            }
+        }
+
+        Ok(overrides)
+    }
+
+    /// Adds a cache directory, replacing the ones from the config file.
+    pub fn cache_dir<S: Into<String>>(&mut self, s: S) -> &mut Self {
+        self.cache_dir.push(s.into());
+        self
+    }
+
+    /// Adds a hook directory on top of the ones from the config file.
+    pub fn hook_dir<S: Into<String>>(&mut self, s: S) -> &mut Self {
+        self.hook_dir.push(s.into());
+        self
+    }
+
+    /// Adds an architecture, replacing the ones from the config file. `auto` is resolved
+    /// to the architecture of this machine.
+    pub fn architecture<S: Into<String>>(&mut self, s: S) -> &mut Self {
+        self.architecture.push(s.into());
+        self
+    }
+
+    /// Sets the database path.
+    pub fn db_path<S: Into<String>>(&mut self, s: S) -> &mut Self {
+        self.db_path = Some(s.into());
+        self
+    }
+
+    /// Sets the GnuPG directory.
+    pub fn gpg_dir<S: Into<String>>(&mut self, s: S) -> &mut Self {
+        self.gpg_dir = Some(s.into());
+        self
+    }
+
+    /// Sets the log file.
+    pub fn log_file<S: Into<String>>(&mut self, s: S) -> &mut Self {
+        self.log_file = Some(s.into());
+        self
+    }
+
+    /// Sets the root directory.
+    pub fn root_dir<S: Into<String>>(&mut self, s: S) -> &mut Self {
+        self.root_dir = Some(s.into());
+        self
+    }
+
+    /// Sets whether color is used.
+    pub fn color(&mut self, color: ColorMode) -> &mut Self {
+        self.color = Some(color);
+        self
+    }
+
+    /// Applies the overrides to a config read with pacman-conf or
+    /// [`read_native`](crate::Options::read_native).
+    ///
+    /// The config only holds the final paths so a database path or log file that is
+    /// pacman's default under the old root is taken to have been left unset. One that was
+    /// set to that same path in the config file is moved under the new root too, which
+    /// pacman would not do.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(root_dir) = &self.root_dir {
+            let old = config.root_dir.as_str();
+            if self.db_path.is_none() && is_default(old, &config.db_path, DB_PATH) {
+                config.db_path = rooted(root_dir, DB_PATH);
+            }
+            if self.log_file.is_none() && is_default(old, &config.log_file, LOG_FILE) {
+                config.log_file = rooted(root_dir, LOG_FILE);
+            }
+            config.root_dir = root_dir.clone();
+        }
+
+        if let Some(db_path) = &self.db_path {
+            config.db_path = db_path.clone();
+        }
+        if let Some(gpg_dir) = &self.gpg_dir {
+            config.gpg_dir = gpg_dir.clone();
+        }
+        if let Some(log_file) = &self.log_file {
+            config.log_file = log_file.clone();
+        }
+        if !self.cache_dir.is_empty() {
+            config.cache_dir = self.cache_dir.clone();
+        }
+        config.hook_dir.extend(self.hook_dir.iter().cloned());
+        if !self.architecture.is_empty() {
+            config.architecture = native::architectures(&self.architecture);
+        }
+
+        config.color = match self.color {
+            Some(ColorMode::Never) => false,
+            Some(ColorMode::Always) => true,
+            Some(ColorMode::Auto) => stdout().is_terminal(),
+            None => config.color,
+        };
+    }
+}
+
+/// Whether a path is pacman's default under the root directory.
+fn is_default(root: &str, path: &str, default: &str) -> bool {
+    path.is_empty() || path == default || path == rooted(root, default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_args() {
+        let overrides = Overrides::from_args([
+            "-Syu",
+            "--cachedir",
+            "/a",
+            "--cachedir=/b",
+            "-r/mnt",
+            "--needed",
+            "--color=never",
+            "-Qb",
+            "/db",
+            "--",
+            "--arch",
+            "i686",
+        ])
+        .unwrap();
+
+        let mut expected = Overrides::new();
+        expected
+            .cache_dir("/a")
+            .cache_dir("/b")
+            .root_dir("/mnt")
+            .color(ColorMode::Never)
+            .db_path("/db");
+        assert_eq!(overrides, expected);
+
+        assert!(Overrides::from_args(["--color=sometimes"]).is_err());
+        assert!(Overrides::from_args(["--dbpath"]).is_err());
+        assert!(Overrides::from_args(["-Sr"]).is_err());
+    }
+
+    #[test]
+    fn apply() {
+        let config = Config {
+            root_dir: "/".into(),
+            db_path: DB_PATH.into(),
+            log_file: "/var/log/custom.log".into(),
+            cache_dir: vec!["/var/cache/pacman/pkg/".into()],
+            hook_dir: vec!["/etc/pacman.d/hooks/".into()],
+            architecture: vec!["x86_64".into()],
+            color: true,
+            ..Config::default()
+        };
+
+        let mut root = config.clone();
+        Overrides::new().root_dir("/mnt/").apply(&mut root);
+        assert_eq!(root.root_dir, "/mnt/");
+        assert_eq!(root.db_path, "/mnt/var/lib/pacman/");
+        assert_eq!(root.log_file, "/var/log/custom.log");
+
+        Overrides::new().root_dir("/chroot").apply(&mut root);
+        assert_eq!(root.db_path, "/chroot/var/lib/pacman/");
+
+        let mut all = config.clone();
+        Overrides::new()
+            .root_dir("/mnt")
+            .db_path("/db")
+            .cache_dir("/a")
+            .cache_dir("/b")
+            .hook_dir("/hooks")
+            .architecture("auto")
+            .architecture("i686")
+            .color(ColorMode::Never)
+            .apply(&mut all);
+        assert_eq!(all.db_path, "/db");
+        assert_eq!(all.cache_dir, ["/a", "/b"]);
+        assert_eq!(all.hook_dir, ["/etc/pacman.d/hooks/", "/hooks"]);
+        assert_eq!(all.architecture, [native::machine(), "i686".into()]);
+        assert!(!all.color);
+
+        let mut unchanged = config.clone();
+        Overrides::new().apply(&mut unchanged);
+        assert_eq!(unchanged, config);
+    }
+}
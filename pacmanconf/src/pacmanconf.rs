@@ -1,14 +1,31 @@
 use cini::{Callback, CallbackKind, Ini};
+use std::ffi::OsStr;
+use std::fmt;
 use std::str;
 use std::str::FromStr;
-use std::{ffi::OsStr, process::Command};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 use crate::error::{Error, ErrorKind, ErrorLine};
+use crate::process;
+
+/// The usual location of pacman.conf, used when the config file is read directly.
+pub(crate) const PACMAN_CONF: &str = "/etc/pacman.conf";
+
+/// The JSON Schema of a serialized [`Config`].
+#[cfg(feature = "serde")]
+pub const JSON_SCHEMA: &str = include_str!("../config.schema.json");
 
 /// A Pacman repository.
 ///
 /// See pacman.conf (5) for information on each field.
+///
+/// With the `serde` feature the fields are serialized under their names here, see
+/// [`Config`].
 #[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 #[non_exhaustive]
 pub struct Repository {
     /// Name
@@ -24,7 +41,15 @@ pub struct Repository {
 /// A pacman config.
 ///
 /// See pacman.conf (5) for information on each field.
+///
+/// With the `serde` feature the fields are serialized under their names here, such as
+/// `db_path` and `ignore_pkg`. These names are stable and are described by
+/// [`JSON_SCHEMA`]. Missing fields are given their `Default` value when deserializing and
+/// unknown fields are ignored, so documents written by other versions of this crate can
+/// still be read.
 #[derive(Clone, Debug, Default, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 #[non_exhaustive]
 pub struct Config {
     /// RootDir
@@ -91,12 +116,70 @@ pub struct Config {
     pub repos: Vec<Repository>,
 }
 
+impl Repository {
+    /// The directives of the repository as pacman-conf prints them.
+    ///
+    /// Each directive is a key and its value, or no value for flags. Directives that take a
+    /// list of values are repeated once per value.
+    pub fn directives(&self) -> Vec<(&'static str, Option<String>)> {
+        let mut directives = Vec::new();
+        push_list(&mut directives, "Usage", &self.usage);
+        push_list(&mut directives, "SigLevel", &self.sig_level);
+        push_list(&mut directives, "Server", &self.servers);
+        directives
+    }
+}
+
+fn push_str(directives: &mut Vec<(&'static str, Option<String>)>, key: &'static str, value: &str) {
+    if !value.is_empty() {
+        directives.push((key, Some(value.into())));
+    }
+}
+
+fn push_list(
+    directives: &mut Vec<(&'static str, Option<String>)>,
+    key: &'static str,
+    values: &[String],
+) {
+    directives.extend(values.iter().map(|v| (key, Some(v.clone()))));
+}
+
+fn push_bool(directives: &mut Vec<(&'static str, Option<String>)>, key: &'static str, value: bool) {
+    if value {
+        directives.push((key, None));
+    }
+}
+
+/// Formats the config in the same format pacman-conf prints it.
+///
+/// The output can be parsed back into a Config using `FromStr`.
+impl fmt::Display for Config {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let sections = std::iter::once(("options", self.directives()))
+            .chain(self.repos.iter().map(|r| (r.name.as_str(), r.directives())));
+
+        for (name, directives) in sections {
+            writeln!(fmt, "[{}]", name)?;
+            for (key, value) in directives {
+                match value {
+                    Some(value) => writeln!(fmt, "{} = {}", key, value)?,
+                    None => writeln!(fmt, "{}", key)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 #[doc(hidden)]
 impl Ini for Config {
     type Err = Error;
 
     fn callback(&mut self, cb: Callback) -> Result<(), Self::Err> {
-        let line = Some(ErrorLine::new(cb.line_number, cb.line));
+        let line = Some(ErrorLine {
+            filename: cb.filename.map(Into::into),
+            ..ErrorLine::new(cb.line_number, cb.line)
+        });
 
         match cb.kind {
             CallbackKind::Section(section) => {
@@ -184,32 +267,8 @@ impl Config {
         config: Option<T>,
         root_dir: Option<T>,
     ) -> Result<String, Error> {
-        let cmd = bin
-            .as_ref()
-            .map(|t| t.as_ref())
-            .unwrap_or_else(|| OsStr::new("pacman-conf"));
-        let mut cmd = Command::new(cmd);
-        if let Some(root) = root_dir {
-            cmd.arg("--root").arg(root);
-        }
-        if let Some(config) = config {
-            cmd.arg("--config").arg(config);
-        }
-
-        let output = cmd.output()?;
-
-        if !output.status.success() {
-            return Err(ErrorKind::Runtime(
-                String::from_utf8(output.stderr).map_err(|e| e.utf8_error())?,
-            )
-            .into());
-        }
-
-        let mut str = String::from_utf8(output.stdout).map_err(|e| e.utf8_error())?;
-        if str.ends_with('\n') {
-            str.pop().unwrap();
-        }
-        Ok(str)
+        let stdout = process::run(&mut process::command(bin, config, root_dir), None)?;
+        process::expanded(stdout)
     }
 
     /// Expand the pacman_conf
@@ -220,7 +279,58 @@ impl Config {
         Self::expand_with_opts(None, Some(config), None)
     }
 
-    fn handle_section(&mut self, section: &str) {
+    /// The directives of the `[options]` section as pacman-conf prints them.
+    ///
+    /// Each directive is a key and its value, or no value for flags. Directives that take a
+    /// list of values are repeated once per value.
+    pub fn directives(&self) -> Vec<(&'static str, Option<String>)> {
+        let mut directives = Vec::new();
+        let d = &mut directives;
+        push_str(d, "RootDir", &self.root_dir);
+        push_str(d, "DBPath", &self.db_path);
+        push_list(d, "CacheDir", &self.cache_dir);
+        push_list(d, "HookDir", &self.hook_dir);
+        push_str(d, "GPGDir", &self.gpg_dir);
+        push_str(d, "LogFile", &self.log_file);
+        push_list(d, "HoldPkg", &self.hold_pkg);
+        push_list(d, "IgnorePkg", &self.ignore_pkg);
+        push_list(d, "IgnoreGroup", &self.ignore_group);
+        push_list(d, "NoUpgrade", &self.no_upgrade);
+        push_list(d, "NoExtract", &self.no_extract);
+        push_list(d, "Architecture", &self.architecture);
+        push_str(d, "XferCommand", &self.xfer_command);
+        push_bool(d, "UseSyslog", self.use_syslog);
+        push_bool(d, "Color", self.color);
+        push_bool(d, "TotalDownload", self.total_download);
+        push_bool(d, "CheckSpace", self.check_space);
+        push_bool(d, "VerbosePkgLists", self.verbose_pkg_lists);
+        push_bool(d, "DisableDownloadTimeout", self.disable_download_timeout);
+        push_bool(d, "ILoveCandy", self.chomp);
+        push_bool(d, "DisableSandbox", self.disable_sandbox);
+        push_bool(
+            d,
+            "DisableSandboxFilesystem",
+            self.disable_sandbox_filesystem,
+        );
+        push_bool(d, "DisableSandboxSyscalls", self.disable_sandbox_syscalls);
+        if self.use_delta != 0.0 {
+            d.push(("UseDelta", Some(self.use_delta.to_string())));
+        }
+        d.push((
+            "ParallelDownloads",
+            Some(self.parallel_downloads.to_string()),
+        ));
+        push_list(d, "CleanMethod", &self.clean_method);
+        push_list(d, "SigLevel", &self.sig_level);
+        push_list(d, "LocalFileSigLevel", &self.local_file_sig_level);
+        push_list(d, "RemoteFileSigLevel", &self.remote_file_sig_level);
+        if let Some(user) = &self.download_user {
+            d.push(("DownloadUser", Some(user.clone())));
+        }
+        directives
+    }
+
+    pub(crate) fn handle_section(&mut self, section: &str) {
         if section != "options" {
             self.repos.push(Repository {
                 name: section.into(),
@@ -229,7 +339,7 @@ impl Config {
         }
     }
 
-    fn handle_directive(
+    pub(crate) fn handle_directive(
         &mut self,
         section: Option<&str>,
         key: &str,
@@ -265,7 +375,7 @@ impl Config {
         Ok(())
     }
 
-    fn handle_option(
+    pub(crate) fn handle_option(
         &mut self,
         section: &str,
         key: &str,
@@ -330,9 +440,8 @@ mod tests {
     use super::*;
     use std::path::Path;
 
-    #[test]
-    fn eq_pacman_conf() {
-        let pacman_conf = Config {
+    fn pacman_conf() -> Config {
+        Config {
             root_dir: "/".into(),
             db_path: "/var/lib/pacman/".into(),
             cache_dir: vec!["/var/cache/pacman/pkg/".into()],
@@ -464,8 +573,12 @@ mod tests {
                     usage: vec!["All".into()],
                 },
             ],
-        };
+        }
+    }
 
+    #[test]
+    fn eq_pacman_conf() {
+        let pacman_conf = pacman_conf();
         assert_eq!(
             pacman_conf.repos,
             Config::from_file("tests/pacman.conf").unwrap().repos
@@ -473,6 +586,80 @@ mod tests {
         assert_eq!(pacman_conf, Config::from_file("tests/pacman.conf").unwrap());
     }
 
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn eq_native() {
+        let config = Config::options()
+            .pacman_conf("tests/pacman.conf")
+            .read_native()
+            .unwrap();
+        assert_eq!(config, pacman_conf());
+    }
+
+    #[test]
+    fn display() {
+        let config = pacman_conf();
+        let output = config.to_string();
+        assert!(output.starts_with("[options]\nRootDir = /\nDBPath = /var/lib/pacman/\n"));
+        assert!(output.contains("\nColor\n"));
+        assert!(output.contains(
+            "\n[core]\nUsage = All\nServer = http://mirror.cyberbits.eu/archlinux/core/os/x86_64\n"
+        ));
+        assert_eq!(Config::from_str(&output).unwrap(), config);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let mut config = pacman_conf();
+        config.repos.reverse();
+        config.use_delta = 0.7;
+
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(serde_json::from_str::<Config>(&json).unwrap(), config);
+
+        let toml = toml::to_string(&config).unwrap();
+        assert_eq!(toml::from_str::<Config>(&toml).unwrap(), config);
+
+        config.download_user = None;
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(json.contains("\"download_user\":null"));
+        assert_eq!(serde_json::from_str::<Config>(&json).unwrap(), config);
+
+        let toml = toml::to_string(&config).unwrap();
+        assert_eq!(toml::from_str::<Config>(&toml).unwrap(), config);
+
+        let config: Config =
+            serde_json::from_str(r#"{"db_path": "/db", "repos": [{"name": "core"}]}"#).unwrap();
+        assert_eq!(config.db_path, "/db");
+        assert_eq!(config.repos[0].name, "core");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_schema() {
+        let schema: serde_json::Value = serde_json::from_str(JSON_SCHEMA).unwrap();
+        let keys = |value: &serde_json::Value| {
+            let mut keys = value
+                .as_object()
+                .unwrap()
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>();
+            keys.sort();
+            keys
+        };
+
+        let config = serde_json::to_value(pacman_conf()).unwrap();
+        assert_eq!(keys(&schema["properties"]), keys(&config));
+
+        let repo = &config["repos"][0];
+        assert_eq!(
+            keys(&schema["$defs"]["repository"]["properties"]),
+            keys(repo)
+        );
+    }
+
     #[test]
     fn test_success() {
         Config::new().unwrap();
@@ -0,0 +1,205 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use crate::Config;
+
+/// The paths of a pacman config.
+///
+/// Unlike the fields of [`Config`] these are not required to be UTF-8.
+/// [`Options::read_paths`](crate::Options::read_paths) reads them straight from
+/// pacman-conf so paths that are not UTF-8 can still be used.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub struct Paths {
+    /// RootDir
+    pub root_dir: PathBuf,
+    /// DBPath
+    pub db_path: PathBuf,
+    /// CacheDir
+    pub cache_dir: Vec<PathBuf>,
+    /// HookDir
+    pub hook_dir: Vec<PathBuf>,
+    /// GPGDir
+    pub gpg_dir: PathBuf,
+    /// LogFile
+    pub log_file: PathBuf,
+}
+
+#[cfg(unix)]
+fn os_string(bytes: &[u8]) -> OsString {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::OsStr::from_bytes(bytes).to_os_string()
+}
+
+#[cfg(not(unix))]
+fn os_string(bytes: &[u8]) -> OsString {
+    String::from_utf8_lossy(bytes).into_owned().into()
+}
+
+fn trim(bytes: &[u8]) -> &[u8] {
+    let start = bytes.iter().position(|b| !b.is_ascii_whitespace());
+    let end = bytes.iter().rposition(|b| !b.is_ascii_whitespace());
+    match (start, end) {
+        (Some(start), Some(end)) => &bytes[start..=end],
+        _ => &[],
+    }
+}
+
+/// Joins an absolute path onto another directory instead of replacing it.
+fn join(dir: &Path, path: &Path) -> PathBuf {
+    dir.join(path.strip_prefix("/").unwrap_or(path))
+}
+
+impl Paths {
+    /// Reads the paths from the output of pacman-conf.
+    ///
+    /// Only the `[options]` section is looked at and values are used byte for byte.
+    pub fn from_bytes(bytes: &[u8]) -> Paths {
+        let mut paths = Paths::default();
+        let mut options = false;
+
+        for line in bytes.split(|&b| b == b'\n').map(trim) {
+            if line.starts_with(b"[") && line.ends_with(b"]") {
+                options = line == b"[options]";
+                continue;
+            }
+            if !options {
+                continue;
+            }
+
+            let eq = match line.iter().position(|&b| b == b'=') {
+                Some(eq) => eq,
+                None => continue,
+            };
+            let value = PathBuf::from(os_string(trim(&line[eq + 1..])));
+
+            match trim(&line[..eq]) {
+                b"RootDir" => paths.root_dir = value,
+                b"DBPath" => paths.db_path = value,
+                b"CacheDir" => paths.cache_dir.push(value),
+                b"HookDir" => paths.hook_dir.push(value),
+                b"GPGDir" => paths.gpg_dir = value,
+                b"LogFile" => paths.log_file = value,
+                _ => (),
+            }
+        }
+
+        paths
+    }
+
+    /// Joins a path onto the root directory, where pacman installs packages to.
+    ///
+    /// ```
+    /// use pacmanconf::Paths;
+    /// use std::path::Path;
+    ///
+    /// let paths = Paths::from_bytes(b"[options]\nRootDir = /mnt\n");
+    /// assert_eq!(paths.in_root("/usr/bin/pacman"), Path::new("/mnt/usr/bin/pacman"));
+    /// ```
+    pub fn in_root<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        join(&self.root_dir, path.as_ref())
+    }
+
+    /// Maps every path into a system mounted at `sysroot`, such as a chroot or an image.
+    ///
+    /// This mirrors pacman's `--sysroot`, where pacman chroots into the directory before
+    /// reading its config so every path refers to a file under it. pacman's `--root` only
+    /// moves where packages are installed along with the default database and log file,
+    /// which is done with [`Overrides::root_dir`](crate::Overrides::root_dir) before the
+    /// paths are read.
+    ///
+    /// ```
+    /// use pacmanconf::Paths;
+    /// use std::path::Path;
+    ///
+    /// let paths = Paths::from_bytes(b"[options]\nRootDir = /\nDBPath = /var/lib/pacman/\n");
+    /// let paths = paths.resolve_in("/mnt");
+    /// assert_eq!(paths.root_dir, Path::new("/mnt/"));
+    /// assert_eq!(paths.db_path, Path::new("/mnt/var/lib/pacman/"));
+    /// ```
+    pub fn resolve_in<P: AsRef<Path>>(&self, sysroot: P) -> Paths {
+        let sysroot = sysroot.as_ref();
+        let join = |path: &PathBuf| join(sysroot, path);
+
+        Paths {
+            root_dir: join(&self.root_dir),
+            db_path: join(&self.db_path),
+            cache_dir: self.cache_dir.iter().map(join).collect(),
+            hook_dir: self.hook_dir.iter().map(join).collect(),
+            gpg_dir: join(&self.gpg_dir),
+            log_file: join(&self.log_file),
+        }
+    }
+}
+
+impl Config {
+    /// The paths of the config as [`Paths`].
+    pub fn paths(&self) -> Paths {
+        let path = |s: &String| PathBuf::from(s);
+
+        Paths {
+            root_dir: path(&self.root_dir),
+            db_path: path(&self.db_path),
+            cache_dir: self.cache_dir.iter().map(path).collect(),
+            hook_dir: self.hook_dir.iter().map(path).collect(),
+            gpg_dir: path(&self.gpg_dir),
+            log_file: path(&self.log_file),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_bytes() {
+        let paths = Paths::from_bytes(
+            b"[options]\nRootDir = /\nDBPath = /var/lib/pacman/\nCacheDir = /a\nCacheDir = /b\n\
+              GPGDir=/etc/pacman.d/gnupg/\nLogFile = /var/log/\xffpacman.log\n\
+              [core]\nDBPath = /nope\n",
+        );
+
+        assert_eq!(paths.root_dir, Path::new("/"));
+        assert_eq!(paths.db_path, Path::new("/var/lib/pacman/"));
+        assert_eq!(paths.cache_dir, [Path::new("/a"), Path::new("/b")]);
+        assert_eq!(paths.gpg_dir, Path::new("/etc/pacman.d/gnupg/"));
+        assert!(paths.hook_dir.is_empty());
+        #[cfg(unix)]
+        assert_eq!(
+            paths.log_file.as_os_str().len(),
+            "/var/log/pacman.log".len() + 1
+        );
+    }
+
+    #[test]
+    fn resolve_in() {
+        let config = Config {
+            root_dir: "/".into(),
+            db_path: "/var/lib/pacman/".into(),
+            cache_dir: vec!["/var/cache/pacman/pkg/".into()],
+            hook_dir: vec!["/etc/pacman.d/hooks/".into()],
+            gpg_dir: "/etc/pacman.d/gnupg/".into(),
+            log_file: "/var/log/pacman.log".into(),
+            ..Config::default()
+        };
+
+        let paths = config.paths().resolve_in("/mnt/image");
+        assert_eq!(paths.root_dir, Path::new("/mnt/image/"));
+        assert_eq!(paths.db_path, Path::new("/mnt/image/var/lib/pacman/"));
+        assert_eq!(
+            paths.cache_dir,
+            [Path::new("/mnt/image/var/cache/pacman/pkg/")]
+        );
+        assert_eq!(
+            paths.hook_dir,
+            [Path::new("/mnt/image/etc/pacman.d/hooks/")]
+        );
+        assert_eq!(paths.gpg_dir, Path::new("/mnt/image/etc/pacman.d/gnupg/"));
+        assert_eq!(paths.log_file, Path::new("/mnt/image/var/log/pacman.log"));
+        assert_eq!(
+            paths.in_root("/etc/os-release"),
+            Path::new("/mnt/image/etc/os-release")
+        );
+    }
+}
@@ -0,0 +1,431 @@
+use std::ffi::OsStr;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, ErrorKind, ErrorLine};
+
+/// How often a child with a timeout is checked on.
+const POLL: Duration = Duration::from_millis(10);
+
+/// Builds the pacman-conf command that dumps a config file.
+pub(crate) fn command<T: AsRef<OsStr>>(
+    bin: Option<T>,
+    config: Option<T>,
+    root_dir: Option<T>,
+) -> Command {
+    let cmd = bin
+        .as_ref()
+        .map(|t| t.as_ref())
+        .unwrap_or_else(|| OsStr::new("pacman-conf"));
+    let mut cmd = Command::new(cmd);
+    if let Some(root) = root_dir {
+        cmd.arg("--root").arg(root);
+    }
+    if let Some(config) = config {
+        cmd.arg("--config").arg(config);
+    }
+    cmd
+}
+
+fn read_all<R: Read + Send + 'static>(mut r: R) -> thread::JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        r.read_to_end(&mut buf).map(|_| buf)
+    })
+}
+
+/// Runs pacman-conf and returns its output as raw bytes.
+///
+/// The child is killed if it has not exited within the timeout.
+pub(crate) fn run(cmd: &mut Command, timeout: Option<Duration>) -> Result<Vec<u8>, Error> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| spawn_error(cmd.get_program(), err))?;
+    let dir = cmd.get_current_dir().map(Path::to_path_buf);
+
+    let stdout = read_all(child.stdout.take().unwrap());
+    let stderr = read_all(child.stderr.take().unwrap());
+
+    let status = match timeout {
+        Some(timeout) => {
+            let start = Instant::now();
+            loop {
+                if let Some(status) = child.try_wait()? {
+                    break status;
+                }
+                if start.elapsed() >= timeout {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(ErrorKind::Timeout(timeout).into());
+                }
+                thread::sleep(POLL);
+            }
+        }
+        None => child.wait()?,
+    };
+
+    let stdout = stdout.join().unwrap()?;
+    let stderr = stderr.join().unwrap()?;
+    output(status, stdout, stderr, dir.as_deref())
+}
+
+/// Runs pacman-conf without blocking and returns its output as raw bytes.
+///
+/// The child is killed if it has not exited within the timeout or if the future is
+/// dropped.
+#[cfg(feature = "tokio")]
+pub(crate) async fn run_async(
+    mut cmd: Command,
+    timeout: Option<Duration>,
+) -> Result<Vec<u8>, Error> {
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let program = cmd.get_program().to_owned();
+    let dir = cmd.get_current_dir().map(Path::to_path_buf);
+    let child = tokio::process::Command::from(cmd)
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|err| spawn_error(&program, err))?;
+    let wait = child.wait_with_output();
+
+    let res = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, wait)
+            .await
+            .map_err(|_| ErrorKind::Timeout(timeout))?,
+        None => wait.await,
+    };
+    let res = res?;
+    output(res.status, res.stdout, res.stderr, dir.as_deref())
+}
+
+fn spawn_error(program: &OsStr, err: io::Error) -> ErrorKind {
+    match err.kind() {
+        io::ErrorKind::NotFound => ErrorKind::BinaryNotFound(program.to_string_lossy().into()),
+        _ => ErrorKind::Io(err),
+    }
+}
+
+/// Checks how pacman-conf exited and returns its stdout if it succeeded.
+///
+/// `dir` is the directory pacman-conf was run in, if it was not run in ours.
+fn output(
+    status: ExitStatus,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    dir: Option<&Path>,
+) -> Result<Vec<u8>, Error> {
+    if !status.success() {
+        let stderr = String::from_utf8(stderr).map_err(|e| e.utf8_error())?;
+        return Err(error(status, stderr, dir));
+    }
+
+    Ok(stdout)
+}
+
+/// Converts the output of pacman-conf to a string without the trailing newline.
+pub(crate) fn expanded(stdout: Vec<u8>) -> Result<String, Error> {
+    let mut str = String::from_utf8(stdout).map_err(|e| e.utf8_error())?;
+    if str.ends_with('\n') {
+        str.pop().unwrap();
+    }
+    Ok(str)
+}
+
+/// Finds the text of a line in a config file along with the section it is in.
+///
+/// Relative filenames are read from `dir` when given, as that is where pacman-conf
+/// resolved them.
+fn line_of(dir: Option<&Path>, filename: &str, number: usize) -> (String, String) {
+    let path = match dir {
+        Some(dir) => dir.join(filename),
+        None => PathBuf::from(filename),
+    };
+    let file = fs::read_to_string(path).unwrap_or_default();
+    let mut section = String::new();
+
+    for (i, line) in file.lines().enumerate().take(number) {
+        let line = line.trim();
+        if i + 1 == number {
+            return (section, line.into());
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.into();
+        }
+    }
+
+    (section, String::new())
+}
+
+/// Turns pacman-conf's error output into an error.
+///
+/// Errors pacman reports against a line of a config file are given the same kind and
+/// line as errors found while parsing in process. Anything else is kept as is along with
+/// the exit status.
+pub(crate) fn error(status: ExitStatus, stderr: String, dir: Option<&Path>) -> Error {
+    parse_error(status, &stderr, dir).unwrap_or_else(|| ErrorKind::Exit(status, stderr).into())
+}
+
+fn parse_error(status: ExitStatus, stderr: &str, dir: Option<&Path>) -> Option<Error> {
+    let message = stderr.lines().find_map(|l| l.strip_prefix("error: "))?;
+    let message = message.strip_prefix("config file ")?;
+
+    if let Some((filename, reason)) = message.split_once(" could not be read: ") {
+        let kind = match reason {
+            "No such file or directory" => io::ErrorKind::NotFound,
+            "Permission denied" => io::ErrorKind::PermissionDenied,
+            _ => io::ErrorKind::Other,
+        };
+        return Some(Error {
+            kind: ErrorKind::Io(io::Error::new(kind, reason)),
+            line: Some(ErrorLine {
+                filename: Some(filename.into()),
+                ..ErrorLine::default()
+            }),
+        });
+    }
+
+    let (filename, message) = message.split_once(", line ")?;
+    let (number, message) = message.split_once(": ")?;
+    let number = number.parse().ok()?;
+    let (section, line) = line_of(dir, filename, number);
+
+    let kind = if let Some(directive) = message.strip_prefix("directive '") {
+        if let Some((key, rest)) = directive.split_once("' in section '") {
+            let section = rest.strip_suffix("' not recognized.")?;
+            ErrorKind::UnknownKey(section.into(), key.into())
+        } else {
+            let key = directive.strip_suffix("' needs a value")?;
+            ErrorKind::MissingValue(section, key.into())
+        }
+    } else if let Some(rest) = message.strip_prefix("invalid value for '") {
+        let (key, value) = rest.split_once("' : '")?;
+        let value = value.strip_suffix('\'')?;
+        ErrorKind::InvalidValue(section, key.into(), value.into())
+    } else if message == "All directives must belong to a section." {
+        let key = line.split('=').next().unwrap_or_default().trim();
+        ErrorKind::NoSection(key.into())
+    } else {
+        ErrorKind::Exit(status, stderr.into())
+    };
+
+    Some(Error {
+        kind,
+        line: Some(ErrorLine {
+            filename: Some(filename.into()),
+            ..ErrorLine::new(number, line)
+        }),
+    })
+}
+
+/// A private file that is removed when dropped.
+pub(crate) struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// Creates a file only the current user can read in `dir` holding `contents`.
+    pub(crate) fn new(dir: &Path, contents: &[u8]) -> io::Result<TempFile> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        loop {
+            let count = COUNT.fetch_add(1, Ordering::Relaxed);
+            let name = format!(".pacmanconf-{}-{}.conf", process::id(), count);
+            let path = dir.join(name);
+
+            let mut file = match options.open(&path) {
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                file => file?,
+            };
+            let tmp = TempFile { path };
+            file.write_all(contents)?;
+            return Ok(tmp);
+        }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Writes a script that stands in for pacman-conf. It runs `body` with the config file it
+/// was given as `$2`.
+#[cfg(all(test, unix))]
+pub(crate) fn fake_pacman_conf(name: &str, body: &str) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let bin = std::env::temp_dir().join(format!("pacmanconf-fake-{}-{}", name, process::id()));
+    let script = format!(
+        "#!/bin/sh\nwhile [ \"$1\" != --config ]; do shift; done\n{}\n",
+        body
+    );
+    fs::write(&bin, script).unwrap();
+    fs::set_permissions(&bin, fs::Permissions::from_mode(0o755)).unwrap();
+    bin
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+
+    #[cfg(unix)]
+    fn status(code: i32) -> ExitStatus {
+        use std::os::unix::process::ExitStatusExt;
+        ExitStatus::from_raw(code << 8)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parse_errors() {
+        let path = std::env::temp_dir().join(format!("pacmanconf-process-{}", process::id()));
+        let mut file = File::create(&path).unwrap();
+        file.write_all(b"Color\n[options]\nDBPath\nParallelDownloads = many\n")
+            .unwrap();
+        let filename = path.to_str().unwrap();
+
+        let err = error(
+            status(1),
+            format!(
+                "error: config file {}, line 3: directive 'DBPath' needs a value\n",
+                filename
+            ),
+            None,
+        );
+        assert!(
+            matches!(&err.kind, ErrorKind::MissingValue(s, k) if s == "options" && k == "DBPath")
+        );
+        let line = err.line.unwrap();
+        assert_eq!(line.filename.as_deref(), Some(filename));
+        assert_eq!((line.number, line.line.as_str()), (3, "DBPath"));
+
+        let err = error(
+            status(1),
+            format!(
+                "error: config file {}, line 4: invalid value for 'ParallelDownloads' : 'many'\n",
+                filename
+            ),
+            None,
+        );
+        assert!(
+            matches!(&err.kind, ErrorKind::InvalidValue(s, k, v) if s == "options" && k == "ParallelDownloads" && v == "many")
+        );
+
+        let err = error(
+            status(1),
+            format!(
+                "error: config file {}, line 1: All directives must belong to a section.\n",
+                filename
+            ),
+            None,
+        );
+        assert!(matches!(&err.kind, ErrorKind::NoSection(k) if k == "Color"));
+
+        let err = error(
+            status(1),
+            format!(
+                "error: config file {}, line 2: bad section name.\n",
+                filename
+            ),
+            None,
+        );
+        assert!(matches!(&err.kind, ErrorKind::Exit(s, _) if s.code() == Some(1)));
+        assert_eq!(err.line.unwrap().number, 2);
+
+        let err = error(
+            status(1),
+            "error: config file /nope could not be read: No such file or directory\n".into(),
+            None,
+        );
+        assert!(matches!(&err.kind, ErrorKind::Io(e) if e.kind() == io::ErrorKind::NotFound));
+        assert_eq!(err.to_string(), "/nope: No such file or directory");
+
+        let err = error(status(2), "something else\n".into(), None);
+        assert!(
+            matches!(&err.kind, ErrorKind::Exit(s, e) if s.code() == Some(2) && e == "something else\n")
+        );
+        assert!(err.line.is_none());
+
+        let dir = path.parent().unwrap();
+        let name = path.file_name().unwrap().to_str().unwrap();
+        let err = error(
+            status(1),
+            format!(
+                "error: config file {}, line 3: directive 'DBPath' needs a value\n",
+                name
+            ),
+            Some(dir),
+        );
+        assert!(
+            matches!(&err.kind, ErrorKind::MissingValue(s, k) if s == "options" && k == "DBPath")
+        );
+        let line = err.line.unwrap();
+        assert_eq!(line.filename.as_deref(), Some(name));
+        assert_eq!(line.line, "DBPath");
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_child() {
+        let err = run(&mut Command::new("/nonexistent/pacman-conf"), None).unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::BinaryNotFound(p) if p == "/nonexistent/pacman-conf")
+        );
+
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "sleep 5"]);
+        let err = run(&mut cmd, Some(Duration::from_millis(50))).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Timeout(_)));
+
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo '[options]'; echo oops >&2; exit 3"]);
+        let err = run(&mut cmd, Some(Duration::from_secs(5))).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Exit(s, e) if s.code() == Some(3) && e == "oops\n"));
+
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo '[options]'"]);
+        assert_eq!(run(&mut cmd, None).unwrap(), b"[options]\n");
+    }
+
+    #[test]
+    fn temp_file() {
+        let dir = std::env::temp_dir();
+        let a = TempFile::new(&dir, b"[options]\n").unwrap();
+        let b = TempFile::new(&dir, b"").unwrap();
+        assert_ne!(a.path(), b.path());
+        assert_eq!(fs::read(a.path()).unwrap(), b"[options]\n");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(a.path()).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let path = a.path().to_path_buf();
+        drop(a);
+        assert!(!path.exists());
+    }
+}
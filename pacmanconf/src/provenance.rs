@@ -0,0 +1,378 @@
+use cini::{Callback, CallbackKind, Ini};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::error::{Error, ErrorKind, ErrorLine};
+use crate::glob::glob;
+use crate::keys;
+use crate::native;
+
+/// The maximum depth of nested Includes, matching pacman.
+const MAX_DEPTH: usize = 10;
+
+/// The location a value was set at.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Origin {
+    /// The file the value was set in
+    pub filename: String,
+    /// The line number the value was set on
+    pub line_number: usize,
+}
+
+/// A directive and the location it was set at.
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Source {
+    /// The section the directive is in
+    pub section: String,
+    /// The key of the directive
+    pub key: String,
+    /// The value of the directive (if any)
+    pub value: Option<String>,
+    /// Where the directive was set
+    pub origin: Origin,
+}
+
+impl Source {
+    /// Checks if this directive sets a value.
+    ///
+    /// For directives that take a list of values such as `IgnorePkg` this checks each
+    /// whitespace separated value individually.
+    pub fn sets(&self, value: &str) -> bool {
+        match self.value.as_deref() {
+            Some(v) if keys::is_list(&self.key) => v.split_whitespace().any(|v| v == value),
+            Some(v) => v == value,
+            None => false,
+        }
+    }
+}
+
+/// Records where each value of a pacman.conf came from.
+///
+/// A [`Config`](crate::Config) produced by pacman-conf has all of its Includes expanded,
+/// losing track of which file set each value. Provenance parses the config file itself,
+/// following Includes, and records the file and line of every directive.
+///
+/// ```
+/// use pacmanconf::Provenance;
+///
+/// # fn main() -> Result<(), pacmanconf::Error> {
+/// let provenance = Provenance::from_file("tests/pacman.conf")?;
+///
+/// let origin = provenance.find_option("IgnorePkg", "linux-ck").unwrap();
+/// assert_eq!(origin.filename, "tests/pacman.conf");
+/// assert_eq!(origin.line_number, 26);
+///
+/// let origin = provenance
+///     .find_server("core", "https://mirror.cyberbits.eu/archlinux/$repo/os/$arch")
+///     .unwrap();
+/// assert_eq!(origin.filename, "tests/mirrorlist");
+/// assert_eq!(origin.line_number, 3);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Provenance {
+    files: Vec<String>,
+    sections: Vec<(String, Origin)>,
+    sources: Vec<Source>,
+}
+
+impl Provenance {
+    /// Reads a config file, following its Includes, and records where each value was set.
+    ///
+    /// Relative Includes are resolved against the current directory, as pacman does.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Provenance, Error> {
+        let path = path.as_ref().to_string_lossy();
+        Self::trace(&path, |path| fs::read_to_string(path))
+    }
+
+    /// Like from_file() but reads each file using `read`.
+    ///
+    /// This allows tracing files that have been modified in memory.
+    pub(crate) fn trace<F>(path: &str, read: F) -> Result<Provenance, Error>
+    where
+        F: FnMut(&str) -> io::Result<String>,
+    {
+        Self::trace_in(None, path, read)
+    }
+
+    /// Like from_file() but for the config file pacman-conf reads after changing root into
+    /// `sysroot`.
+    ///
+    /// The file and its Includes are read from under `sysroot` and recorded with the paths
+    /// they have on this system.
+    pub(crate) fn from_sysroot(sysroot: &str, path: &str) -> Result<Provenance, Error> {
+        let path = in_sysroot(sysroot, path);
+        Self::trace_in(Some(sysroot), &path, |path| fs::read_to_string(path))
+    }
+
+    /// Like from_sysroot() but for config file contents held in memory, whose Includes
+    /// are read from under `sysroot` if one is given.
+    pub(crate) fn from_contents(
+        sysroot: Option<&str>,
+        contents: &str,
+    ) -> Result<Provenance, Error> {
+        Self::trace_in(sysroot, "", |path| match path {
+            "" => Ok(contents.into()),
+            _ => fs::read_to_string(path),
+        })
+    }
+
+    fn trace_in<F>(sysroot: Option<&str>, path: &str, read: F) -> Result<Provenance, Error>
+    where
+        F: FnMut(&str) -> io::Result<String>,
+    {
+        let mut tracer = Tracer {
+            depth: 0,
+            sysroot: sysroot.map(Into::into),
+            read,
+            provenance: Provenance {
+                files: vec![path.into()],
+                ..Default::default()
+            },
+        };
+        let ini = (tracer.read)(path)?;
+        tracer.parse(Some(path), &ini)?;
+        Ok(tracer.provenance)
+    }
+
+    /// Every file that was read, in the order they were first read.
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    /// Every section header that was read and where it was, in order.
+    pub fn sections(&self) -> &[(String, Origin)] {
+        &self.sections
+    }
+
+    /// Every directive that was read, in order.
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+
+    /// The directives that set an option.
+    pub fn option<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a Source> + 'a {
+        self.section("options").filter(move |s| s.key == key)
+    }
+
+    /// The directives within a section.
+    pub fn section<'a>(&'a self, section: &'a str) -> impl Iterator<Item = &'a Source> + 'a {
+        self.sources.iter().filter(move |s| s.section == section)
+    }
+
+    /// Finds where an option was set to a value.
+    pub fn find_option(&self, key: &str, value: &str) -> Option<&Origin> {
+        self.sources
+            .iter()
+            .find(|s| s.section == "options" && s.key == key && s.sets(value))
+            .map(|s| &s.origin)
+    }
+
+    /// Finds where a repository was declared.
+    pub fn find_repo(&self, name: &str) -> Option<&Origin> {
+        self.sections
+            .iter()
+            .find(|(section, _)| section == name)
+            .map(|(_, origin)| origin)
+    }
+
+    /// Finds where a server was added to a repository.
+    ///
+    /// The server may be given as it appears in the config file or with `$repo` and `$arch`
+    /// already expanded, as pacman-conf returns it.
+    pub fn find_server(&self, repo: &str, server: &str) -> Option<&Origin> {
+        let arch = self.arch();
+
+        self.sources
+            .iter()
+            .filter(|s| s.section == repo && s.key == "Server")
+            .find(|s| {
+                s.value.as_deref().is_some_and(|v| {
+                    v == server || v.replace("$repo", repo).replace("$arch", &arch) == server
+                })
+            })
+            .map(|s| &s.origin)
+    }
+
+    /// The architecture `$arch` expands to.
+    ///
+    /// `Architecture = auto` expands to the machine's architecture.
+    fn arch(&self) -> String {
+        let values = self
+            .option("Architecture")
+            .filter_map(|s| s.value.as_deref())
+            .collect::<Vec<_>>();
+        native::architectures(&values)
+            .into_iter()
+            .next()
+            .unwrap_or_else(native::machine)
+    }
+}
+
+/// The path under `sysroot` that `path` refers to once pacman-conf has changed root.
+fn in_sysroot(sysroot: &str, path: &str) -> String {
+    Path::new(sysroot)
+        .join(path.trim_start_matches('/'))
+        .to_string_lossy()
+        .into_owned()
+}
+
+struct Tracer<F> {
+    depth: usize,
+    sysroot: Option<String>,
+    read: F,
+    provenance: Provenance,
+}
+
+impl<F> Ini for Tracer<F>
+where
+    F: FnMut(&str) -> io::Result<String>,
+{
+    type Err = Error;
+
+    fn callback(&mut self, cb: Callback) -> Result<(), Self::Err> {
+        let Callback {
+            filename,
+            line,
+            line_number,
+            kind,
+        } = cb;
+        let origin = Origin {
+            filename: filename.unwrap_or_default().into(),
+            line_number,
+        };
+        let error = |kind| Error {
+            kind,
+            line: Some(ErrorLine {
+                filename: filename.map(Into::into),
+                ..ErrorLine::new(line_number, line)
+            }),
+        };
+
+        match kind {
+            CallbackKind::Section(section) => {
+                self.provenance.sections.push((section.into(), origin));
+            }
+            CallbackKind::Directive(None, key, _) => {
+                return Err(error(ErrorKind::NoSection(key.into())));
+            }
+            CallbackKind::Directive(Some(section), key, value) => {
+                self.provenance.sources.push(Source {
+                    section: section.into(),
+                    key: key.into(),
+                    value: value.map(Into::into),
+                    origin,
+                });
+
+                if key == "Include" {
+                    let value = value.ok_or_else(|| {
+                        error(ErrorKind::MissingValue(section.into(), key.into()))
+                    })?;
+
+                    if self.depth >= MAX_DEPTH {
+                        let kind =
+                            ErrorKind::InvalidValue(section.into(), key.into(), value.into());
+                        return Err(error(kind));
+                    }
+
+                    let pattern = match &self.sysroot {
+                        Some(sysroot) => in_sysroot(sysroot, value),
+                        None => value.into(),
+                    };
+                    for path in glob(&pattern) {
+                        let path = path.to_string_lossy().into_owned();
+                        let ini = (self.read)(&path).map_err(|e| error(e.into()))?;
+                        if !self.provenance.files.contains(&path) {
+                            self.provenance.files.push(path.clone());
+                        }
+
+                        self.depth += 1;
+                        let res = self.parse_with_section(Some(section), Some(&path), &ini);
+                        self.depth -= 1;
+                        res?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pacman_conf() {
+        let provenance = Provenance::from_file("tests/pacman.conf").unwrap();
+
+        assert_eq!(
+            provenance.files(),
+            ["tests/pacman.conf", "tests/mirrorlist"]
+        );
+        assert_eq!(
+            provenance.find_option("HoldPkg", "glibc"),
+            Some(&Origin {
+                filename: "tests/pacman.conf".into(),
+                line_number: 18
+            })
+        );
+        assert_eq!(provenance.find_option("HoldPkg", "gli"), None);
+        assert_eq!(
+            provenance
+                .find_option("DBPath", "/var/lib/pacman/")
+                .unwrap()
+                .line_number,
+            13
+        );
+        assert_eq!(provenance.find_repo("multilib").unwrap().line_number, 68);
+        assert_eq!(provenance.find_repo("cake"), None);
+
+        let server = format!(
+            "http://mirrors.neusoft.edu.cn/archlinux/testing/os/{}",
+            native::machine()
+        );
+        assert_eq!(
+            provenance.find_server("testing", &server),
+            Some(&Origin {
+                filename: "tests/mirrorlist".into(),
+                line_number: 5
+            })
+        );
+        assert_eq!(
+            provenance.find_server(
+                "core",
+                "http://mirror.cyberbits.eu/archlinux/$repo/os/$arch"
+            ),
+            Some(&Origin {
+                filename: "tests/mirrorlist".into(),
+                line_number: 1
+            })
+        );
+        assert_eq!(provenance.find_server("core", "http://example.com"), None);
+        assert_eq!(provenance.section("extra").count(), 6);
+    }
+
+    #[test]
+    fn errors() {
+        let err = Provenance::from_file("tests/nope.conf").unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Io(_)));
+
+        let mut tracer = Tracer {
+            depth: 0,
+            sysroot: None,
+            read: |path: &str| fs::read_to_string(path),
+            provenance: Provenance::default(),
+        };
+        let err = tracer
+            .parse(Some("pacman.conf"), "[options]\nInclude = tests/nope\n")
+            .unwrap_err();
+        let line = err.line.unwrap();
+        assert!(matches!(err.kind, ErrorKind::Io(_)));
+        assert_eq!(line.filename.as_deref(), Some("pacman.conf"));
+        assert_eq!(line.number, 2);
+    }
+}
@@ -0,0 +1,391 @@
+use std::fs;
+use std::path::Path;
+
+use crate::document::{find_section, validate_option, LineKind};
+use crate::error::{Error, ErrorKind};
+use crate::keys;
+use crate::{Document, Origin, Provenance};
+
+/// An editing session over a pacman.conf and every file it Includes.
+///
+/// Where a [`Document`] edits a single file, a session loads the whole Include tree and
+/// applies each edit to the file that defines the value being changed. Setting an option
+/// that was set in a drop-in config edits the drop-in and removing a server that came from
+/// a mirrorlist comments it out in the mirrorlist. New values are added to the file that
+/// declares their section.
+///
+/// Nothing is written until [`save`](Session::save) is called, which only writes the files
+/// that have changed.
+///
+/// ```no_run
+/// use pacmanconf::Session;
+///
+/// # fn main() -> Result<(), pacmanconf::Error> {
+/// let mut session = Session::open("/etc/pacman.conf")?;
+/// session.set_option("ParallelDownloads", "10")?;
+/// session.remove_server("core", "https://geo.mirror.pkgbuild.com/core/os/x86_64")?;
+/// session.enable_repo("multilib")?;
+///
+/// for file in session.save()? {
+///     println!("wrote {}", file);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Session {
+    documents: Vec<Document>,
+    original: Vec<String>,
+}
+
+impl Session {
+    /// Opens a config file and every file it Includes.
+    ///
+    /// Relative Includes are resolved against the current directory, as pacman does.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Session, Error> {
+        let provenance = Provenance::from_file(path)?;
+        let documents = provenance
+            .files()
+            .iter()
+            .map(Document::from_file)
+            .collect::<Result<Vec<_>, _>>()?;
+        let original = documents.iter().map(ToString::to_string).collect();
+
+        Ok(Session {
+            documents,
+            original,
+        })
+    }
+
+    /// Every document in the session. The first document is the top level config file.
+    pub fn documents(&self) -> &[Document] {
+        &self.documents
+    }
+
+    /// Gets a document by its filename.
+    pub fn document(&self, filename: &str) -> Option<&Document> {
+        self.documents
+            .iter()
+            .find(|doc| doc.filename() == Some(filename))
+    }
+
+    /// The documents that have been changed since they were last read or saved.
+    pub fn modified(&self) -> impl Iterator<Item = &Document> {
+        self.documents
+            .iter()
+            .zip(&self.original)
+            .filter(|(doc, original)| doc.to_string() != **original)
+            .map(|(doc, _)| doc)
+    }
+
+    /// Records where each value was set, taking any unsaved edits into account.
+    pub fn provenance(&self) -> Result<Provenance, Error> {
+        let root = self.documents[0].filename().unwrap_or_default();
+        Provenance::trace(root, |path| match self.document(path) {
+            Some(doc) => Ok(doc.to_string()),
+            None => fs::read_to_string(path),
+        })
+    }
+
+    /// Writes every modified document back to its file.
+    ///
+    /// Each file is replaced atomically, see [`Document::save_as`]. Returns the filenames
+    /// that were written.
+    pub fn save(&mut self) -> Result<Vec<String>, Error> {
+        let mut written = Vec::new();
+
+        for (doc, original) in self.documents.iter().zip(&mut self.original) {
+            let contents = doc.to_string();
+            if contents != *original {
+                doc.save()?;
+                *original = contents;
+                written.push(doc.filename().unwrap_or_default().to_string());
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// Sets an option in the file that defines it.
+    ///
+    /// The first place the option is set is updated and any others are commented out.
+    /// If the option is not set anywhere then a commented out occurrence is uncommented
+    /// or the option is added to the top level config file.
+    /// See [`Document::set_option`].
+    pub fn set_option<'a, V: Into<Option<&'a str>>>(
+        &mut self,
+        key: &str,
+        value: V,
+    ) -> Result<bool, Error> {
+        let value = value.into();
+        validate_option(key, value)?;
+
+        let provenance = self.provenance()?;
+        let mut sources = provenance.option(key);
+
+        if let Some(first) = sources.next() {
+            let mut changed = false;
+            if first.value.as_deref() != value {
+                self.edit(&first.origin, |doc, i| doc.set_value(i, key, value))?;
+                changed = true;
+            }
+            for source in sources {
+                self.edit(&source.origin, |doc, i| doc.comment_line(i))?;
+                changed = true;
+            }
+            return Ok(changed);
+        }
+
+        let commented = self.documents.iter_mut().find(|doc| {
+            doc.classify().iter().any(|line| {
+                line.commented
+                    && line.section.as_deref() == Some("options")
+                    && matches!(&line.kind, Some(LineKind::Directive(k, _)) if k == key)
+            })
+        });
+
+        match commented {
+            Some(doc) => doc.set_option(key, value),
+            None => self.documents[0].set_option(key, value),
+        }
+    }
+
+    /// Unsets an option by commenting it out in every file that sets it.
+    ///
+    /// Returns false if the option was not set.
+    pub fn unset_option(&mut self, key: &str) -> Result<bool, Error> {
+        if keys::option_key(key).is_none() {
+            return Err(ErrorKind::UnknownKey("options".into(), key.into()).into());
+        }
+
+        let provenance = self.provenance()?;
+        let mut changed = false;
+
+        for source in provenance.option(key) {
+            self.edit(&source.origin, |doc, i| doc.comment_line(i))?;
+            changed = true;
+        }
+
+        Ok(changed)
+    }
+
+    /// Enables a repository in the file where it is commented out.
+    /// See [`Document::enable_repo`].
+    pub fn enable_repo(&mut self, name: &str) -> Result<bool, Error> {
+        if self.provenance()?.find_repo(name).is_some() {
+            return Ok(false);
+        }
+
+        let doc = self
+            .documents
+            .iter_mut()
+            .find(|doc| find_section(&doc.classify(), true, name).is_some())
+            .ok_or_else(|| ErrorKind::NoRepo(name.into()))?;
+        doc.enable_repo(name)
+    }
+
+    /// Disables a repository in the file that declares it.
+    /// See [`Document::disable_repo`].
+    pub fn disable_repo(&mut self, name: &str) -> Result<bool, Error> {
+        let provenance = self.provenance()?;
+
+        match provenance.find_repo(name) {
+            Some(origin) => {
+                let doc = self.document_mut(&origin.filename)?;
+                doc.disable_repo(name)
+            }
+            None => {
+                let commented = self
+                    .documents
+                    .iter()
+                    .any(|doc| find_section(&doc.classify(), true, name).is_some());
+                if commented {
+                    Ok(false)
+                } else {
+                    Err(ErrorKind::NoRepo(name.into()).into())
+                }
+            }
+        }
+    }
+
+    /// Adds a server to the end of a repository in the file that declares the repository.
+    ///
+    /// Returns false if the repository already has this server.
+    pub fn add_server(&mut self, repo: &str, server: &str) -> Result<bool, Error> {
+        let provenance = self.provenance()?;
+
+        if provenance.find_server(repo, server).is_some() {
+            return Ok(false);
+        }
+
+        let header = provenance
+            .find_repo(repo)
+            .ok_or_else(|| ErrorKind::NoRepo(repo.into()))?;
+        let last = provenance
+            .section(repo)
+            .filter(|s| s.origin.filename == header.filename)
+            .filter(|s| s.origin.line_number > header.line_number)
+            .map(|s| s.origin.line_number)
+            .max()
+            .unwrap_or(header.line_number);
+
+        let doc = self.document_mut(&header.filename)?;
+        doc.insert_line(last, format!("Server = {}", server));
+        Ok(true)
+    }
+
+    /// Removes a server from a repository by commenting it out in the file that defines it.
+    ///
+    /// The server may be given as it appears in the config file or with `$repo` and `$arch`
+    /// expanded. Note that servers in a mirrorlist are shared by every repository that
+    /// Includes the mirrorlist.
+    ///
+    /// Returns false if the repository does not have this server.
+    pub fn remove_server(&mut self, repo: &str, server: &str) -> Result<bool, Error> {
+        let mut changed = false;
+
+        while let Some(origin) = self.provenance()?.find_server(repo, server).cloned() {
+            self.edit(&origin, |doc, i| doc.comment_line(i))?;
+            changed = true;
+        }
+
+        Ok(changed)
+    }
+
+    fn document_mut(&mut self, filename: &str) -> Result<&mut Document, Error> {
+        let pos = self
+            .documents
+            .iter()
+            .position(|doc| doc.filename() == Some(filename));
+
+        let pos = match pos {
+            Some(pos) => pos,
+            None => {
+                let doc = Document::from_file(filename)?;
+                self.original.push(doc.to_string());
+                self.documents.push(doc);
+                self.documents.len() - 1
+            }
+        };
+
+        Ok(&mut self.documents[pos])
+    }
+
+    fn edit<F: FnOnce(&mut Document, usize)>(
+        &mut self,
+        origin: &Origin,
+        f: F,
+    ) -> Result<(), Error> {
+        let doc = self.document_mut(&origin.filename)?;
+        f(doc, origin.line_number - 1);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::native;
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+
+    fn tempdir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("pacmanconf-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn edit_include_tree() {
+        let dir = tempdir("session");
+        let pacman_conf = dir.join("pacman.conf");
+        let mirrorlist = dir.join("mirrorlist");
+        let dropin = dir.join("conf.d/ignore.conf");
+
+        fs::write(
+            &pacman_conf,
+            format!(
+                "[options]
+HoldPkg = pacman glibc
+#Color
+Include = {dir}/conf.d/*.conf
+
+[core]
+Include = {dir}/mirrorlist
+
+#[multilib]
+#Include = {dir}/mirrorlist
+",
+                dir = dir.display()
+            ),
+        )
+        .unwrap();
+        fs::write(
+            &mirrorlist,
+            "Server = https://a.example/$repo/os/$arch\nServer = https://b.example/$repo/os/$arch\n",
+        )
+        .unwrap();
+        fs::write(&dropin, "IgnorePkg = linux\nParallelDownloads = 5\n").unwrap();
+
+        let mut session = Session::open(&pacman_conf).unwrap();
+        assert_eq!(session.documents().len(), 3);
+        assert!(session.save().unwrap().is_empty());
+
+        let server = format!("https://b.example/core/os/{}", native::machine());
+        assert!(session.set_option("ParallelDownloads", "10").unwrap());
+        assert!(session.set_option("Color", None).unwrap());
+        assert!(!session.set_option("IgnorePkg", "linux").unwrap());
+        assert!(session.remove_server("core", &server).unwrap());
+        assert!(!session.remove_server("core", &server).unwrap());
+        assert!(session
+            .add_server("core", "https://c.example/$repo/os/$arch")
+            .unwrap());
+        assert!(!session
+            .add_server("core", "https://c.example/$repo/os/$arch")
+            .unwrap());
+        assert!(session.enable_repo("multilib").unwrap());
+        assert!(session.disable_repo("nope").is_err());
+        assert_eq!(session.modified().count(), 3);
+
+        let mut written = session.save().unwrap();
+        written.sort();
+        let mut expected = [&pacman_conf, &mirrorlist, &dropin]
+            .iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(written, expected);
+        assert_eq!(session.modified().count(), 0);
+
+        assert_eq!(
+            fs::read_to_string(&pacman_conf).unwrap(),
+            format!(
+                "[options]
+HoldPkg = pacman glibc
+Color
+Include = {dir}/conf.d/*.conf
+
+[core]
+Include = {dir}/mirrorlist
+Server = https://c.example/$repo/os/$arch
+
+[multilib]
+Include = {dir}/mirrorlist
+",
+                dir = dir.display()
+            )
+        );
+        assert_eq!(
+            fs::read_to_string(&mirrorlist).unwrap(),
+            "Server = https://a.example/$repo/os/$arch\n#Server = https://b.example/$repo/os/$arch\n"
+        );
+        assert_eq!(
+            fs::read_to_string(&dropin).unwrap(),
+            "IgnorePkg = linux\nParallelDownloads = 10\n"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+use crate::inotify::Inotify;
+use crate::{Config, Error, Options};
+
+/// A hash of a file's contents when the config was read. None if it could not be read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Stamp(Option<u64>);
+
+impl Stamp {
+    fn of(path: &str) -> Stamp {
+        Stamp(fs::read(path).ok().map(|contents| {
+            let mut hasher = DefaultHasher::new();
+            contents.hash(&mut hasher);
+            hasher.finish()
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    config: Arc<Config>,
+    files: Vec<(String, Stamp)>,
+    subscribers: Vec<Sender<Arc<Config>>>,
+    // set when a change was seen but the config could not be read again
+    dirty: bool,
+    #[cfg(target_os = "linux")]
+    inotify: Option<Arc<Inotify>>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    options: Options,
+    state: Mutex<State>,
+}
+
+/// A config that is read once and shared, and read again when its files change.
+///
+/// The config is read with pacman-conf using [`Options`] and kept in an [`Arc`], so
+/// [`get`](SharedConfig::get) is cheap while nothing changes. The handle can be cloned
+/// and sent to other threads, every clone sharing the same config.
+///
+/// The contents of pacman.conf and every file it Includes are hashed, and the config is
+/// read again when any of them change. On Linux inotify is used to tell when to look at
+/// the files, otherwise they are looked at on every call to `get`. Files that start
+/// matching a wildcard Include are only noticed once the config is read again for another
+/// reason.
+///
+/// ```no_run
+/// use pacmanconf::{Config, SharedConfig};
+/// use std::time::Duration;
+///
+/// # fn main() -> Result<(), pacmanconf::Error> {
+/// let shared = SharedConfig::new(Config::options())?;
+/// let reloads = shared.subscribe();
+/// shared.watch(Duration::from_secs(1));
+///
+/// for config in reloads {
+///     println!("pacman.conf changed, {} repos", config.repos.len());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SharedConfig {
+    inner: Arc<Inner>,
+}
+
+/// Reads the config and records the files it was read from.
+fn load(options: &Options) -> Result<State, Error> {
+    // the files are hashed before the config is read so a change made while reading is
+    // seen next time
+    let files = options.provenance()?.files().to_vec();
+    let files = files
+        .into_iter()
+        .map(|file| {
+            let stamp = Stamp::of(&file);
+            (file, stamp)
+        })
+        .collect::<Vec<_>>();
+
+    #[cfg(target_os = "linux")]
+    let inotify = Inotify::new(files.iter().map(|(file, _)| file))
+        .ok()
+        .map(Arc::new);
+
+    let config = Arc::new(options.read()?);
+
+    Ok(State {
+        config,
+        files,
+        subscribers: Vec::new(),
+        dirty: false,
+        #[cfg(target_os = "linux")]
+        inotify,
+    })
+}
+
+impl SharedConfig {
+    /// Reads the config using `options`.
+    pub fn new(options: Options) -> Result<SharedConfig, Error> {
+        let state = load(&options)?;
+        Ok(SharedConfig {
+            inner: Arc::new(Inner {
+                options,
+                state: Mutex::new(state),
+            }),
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.inner.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The config, read again first if any of its files changed.
+    ///
+    /// If the config can not be read again the error is returned and the next call tries
+    /// again.
+    pub fn get(&self) -> Result<Arc<Config>, Error> {
+        {
+            let mut state = self.state();
+
+            #[cfg(target_os = "linux")]
+            let check = match &state.inotify {
+                Some(inotify) => inotify.changed() || state.dirty,
+                None => true,
+            };
+            #[cfg(not(target_os = "linux"))]
+            let check = true;
+
+            if !check || !state.files.iter().any(|(f, stamp)| Stamp::of(f) != *stamp) {
+                state.dirty = false;
+                return Ok(state.config.clone());
+            }
+            state.dirty = true;
+        }
+
+        self.reload()
+    }
+
+    /// The config as it was last read, without checking its files.
+    pub fn cached(&self) -> Arc<Config> {
+        self.state().config.clone()
+    }
+
+    /// Reads the config again even if none of its files changed.
+    pub fn reload(&self) -> Result<Arc<Config>, Error> {
+        // pacman-conf is run without holding the lock so other threads can still get the
+        // last config while it runs
+        let new = load(&self.inner.options)?;
+
+        let mut state = self.state();
+        state.config = new.config;
+        state.files = new.files;
+        state.dirty = false;
+        #[cfg(target_os = "linux")]
+        {
+            state.inotify = new.inotify;
+        }
+
+        let config = state.config.clone();
+        state.subscribers.retain(|s| s.send(config.clone()).is_ok());
+        Ok(config)
+    }
+
+    /// The files the config was read from.
+    pub fn files(&self) -> Vec<String> {
+        self.state().files.iter().map(|(f, _)| f.clone()).collect()
+    }
+
+    /// Returns a receiver that is sent the config each time it is read again.
+    pub fn subscribe(&self) -> Receiver<Arc<Config>> {
+        let (tx, rx) = mpsc::channel();
+        self.state().subscribers.push(tx);
+        rx
+    }
+
+    /// Starts a thread that reads the config again whenever its files change, so that
+    /// subscribers are told about changes without anyone calling [`get`](SharedConfig::get).
+    ///
+    /// The files are looked at every `interval`, or as soon as they change when inotify is
+    /// used. If the config can not be read the last good config is kept. The thread exits
+    /// once every clone of the handle has been dropped.
+    pub fn watch(&self, interval: Duration) -> JoinHandle<()> {
+        let inner = Arc::downgrade(&self.inner);
+
+        thread::spawn(move || loop {
+            #[cfg(target_os = "linux")]
+            {
+                let inotify = match inner.upgrade() {
+                    Some(inner) => SharedConfig { inner }.state().inotify.clone(),
+                    None => return,
+                };
+                match inotify {
+                    Some(inotify) => {
+                        inotify.wait(interval);
+                    }
+                    None => thread::sleep(interval),
+                }
+            }
+            #[cfg(not(target_os = "linux"))]
+            thread::sleep(interval);
+
+            match inner.upgrade() {
+                Some(inner) => {
+                    let _ = SharedConfig { inner }.get();
+                }
+                None => return,
+            }
+        })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::process;
+    use std::env;
+    use std::path::PathBuf;
+
+    fn tempdir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("pacmanconf-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("conf.d")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn reload() {
+        let dir = tempdir("shared");
+        let pacman_conf = dir.join("pacman.conf");
+        let include = dir.join("conf.d/repos.conf");
+        fs::write(
+            &pacman_conf,
+            format!("[options]\nColor\nInclude = {}\n", include.display()),
+        )
+        .unwrap();
+        fs::write(&include, "[core]\nServer = file:///a\n").unwrap();
+
+        // expands the one level of Include the test uses
+        let bin = process::fake_pacman_conf(
+            "shared",
+            "grep -v Include \"$2\"; cat $(sed -n 's/^Include = //p' \"$2\")",
+        );
+        let mut options = Config::options();
+        options
+            .pacman_conf_bin(bin.to_str().unwrap())
+            .pacman_conf(pacman_conf.to_str().unwrap());
+
+        let shared = SharedConfig::new(options).unwrap();
+        assert_eq!(shared.files().len(), 2);
+        let first = shared.get().unwrap();
+        assert_eq!(first.repos[0].servers, ["file:///a"]);
+        assert!(Arc::ptr_eq(&first, &shared.get().unwrap()));
+
+        let reloads = shared.subscribe();
+        let watcher = shared.watch(Duration::from_millis(10));
+
+        fs::write(&include, "[core]\nServer = file:///b\n").unwrap();
+        let config = reloads.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(config.repos[0].servers, ["file:///b"]);
+        assert!(Arc::ptr_eq(&config, &shared.cached()));
+
+        fs::write(&include, "[core]\nServer\n").unwrap();
+        assert!(shared.get().is_err());
+        assert_eq!(shared.cached().repos[0].servers, ["file:///b"]);
+        fs::write(&include, "[core]\nServer = file:///c\n").unwrap();
+        assert_eq!(shared.get().unwrap().repos[0].servers, ["file:///c"]);
+
+        drop(shared);
+        watcher.join().unwrap();
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_file(bin).unwrap();
+    }
+
+    #[test]
+    fn reload_unlocked() {
+        let dir = tempdir("shared-unlocked");
+        let pacman_conf = dir.join("pacman.conf");
+        let slow = dir.join("slow");
+        fs::write(&pacman_conf, "[options]\nColor\n").unwrap();
+
+        let bin = process::fake_pacman_conf(
+            "shared-unlocked",
+            &format!("[ -e {} ] && sleep 1; cat \"$2\"", slow.display()),
+        );
+        let mut options = Config::options();
+        options
+            .pacman_conf_bin(bin.to_str().unwrap())
+            .pacman_conf(pacman_conf.to_str().unwrap());
+
+        let shared = SharedConfig::new(options).unwrap();
+        fs::write(&slow, "").unwrap();
+        let reloading = shared.clone();
+        let reload = thread::spawn(move || reloading.reload().unwrap());
+
+        thread::sleep(Duration::from_millis(100));
+        let start = std::time::Instant::now();
+        assert!(shared.cached().color);
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        reload.join().unwrap();
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_file(bin).unwrap();
+    }
+}
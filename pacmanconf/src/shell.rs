@@ -0,0 +1,186 @@
+use std::fmt;
+
+use crate::diff::{lists, repo_lists};
+use crate::error::{Error, ErrorKind};
+use crate::Config;
+
+/// Quotes a string so that a POSIX shell reads it back unchanged.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Converts a key or repository name into a shell variable name.
+fn var(prefix: &str, name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect::<String>();
+    format!("{}{}", prefix, name)
+}
+
+fn write_array(fmt: &mut fmt::Formatter, name: &str, values: &[String]) -> fmt::Result {
+    let values = values.iter().map(|v| quote(v)).collect::<Vec<_>>();
+    writeln!(fmt, "{}=({})", name, values.join(" "))
+}
+
+/// A config formatted as shell variable assignments.
+///
+/// See [`Config::shell`].
+#[derive(Clone, Copy, Debug)]
+pub struct Shell<'a> {
+    config: &'a Config,
+}
+
+impl fmt::Display for Shell<'_> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let config = self.config;
+        let download_user = config.download_user.as_deref().unwrap_or_default();
+        let values = [
+            ("RootDir", config.root_dir.clone()),
+            ("DBPath", config.db_path.clone()),
+            ("GPGDir", config.gpg_dir.clone()),
+            ("LogFile", config.log_file.clone()),
+            ("XferCommand", config.xfer_command.clone()),
+            ("DownloadUser", download_user.to_string()),
+            ("UseDelta", config.use_delta.to_string()),
+            ("ParallelDownloads", config.parallel_downloads.to_string()),
+        ];
+        let flags = [
+            ("UseSyslog", config.use_syslog),
+            ("Color", config.color),
+            ("TotalDownload", config.total_download),
+            ("CheckSpace", config.check_space),
+            ("VerbosePkgLists", config.verbose_pkg_lists),
+            ("DisableDownloadTimeout", config.disable_download_timeout),
+            ("DisableSandbox", config.disable_sandbox),
+            (
+                "DisableSandboxFilesystem",
+                config.disable_sandbox_filesystem,
+            ),
+            ("DisableSandboxSyscalls", config.disable_sandbox_syscalls),
+            ("ILoveCandy", config.chomp),
+        ];
+
+        for (key, value) in &values {
+            writeln!(fmt, "{}={}", var("PACMAN_", key), quote(value))?;
+        }
+        for (key, set) in &flags {
+            writeln!(fmt, "{}={}", var("PACMAN_", key), *set as u8)?;
+        }
+        for (key, values) in lists(config) {
+            write_array(fmt, &var("PACMAN_", key), values)?;
+        }
+
+        let names = config
+            .repos
+            .iter()
+            .map(|r| r.name.clone())
+            .collect::<Vec<_>>();
+        write_array(fmt, "PACMAN_REPOS", &names)?;
+        for repo in &config.repos {
+            let prefix = var("PACMAN_REPO_", &repo.name) + "_";
+            for (key, values) in repo_lists(repo) {
+                write_array(fmt, &var(&prefix, key), values)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Formats the config as shell variable assignments, so that a shell script can read
+    /// the whole config with a single `eval`.
+    ///
+    /// Each option becomes a variable named after its key in uppercase with a `PACMAN_`
+    /// prefix. Options that take a list of values are bash arrays and flags are set to 1
+    /// or 0. `PACMAN_REPOS` lists the repositories and each repository's Server, SigLevel
+    /// and Usage are arrays named `PACMAN_REPO_<NAME>_SERVER` and so on, where characters
+    /// in the name that are not valid in a variable name are replaced with `_`.
+    ///
+    /// Every value is single quoted so spaces and glob characters are kept as they are.
+    ///
+    /// An error is returned if two repositories would be given the same variable names,
+    /// such as `core-testing` and `core_testing`, as one would overwrite the other.
+    ///
+    /// ```
+    /// use pacmanconf::Config;
+    ///
+    /// # fn main() -> Result<(), pacmanconf::Error> {
+    /// let config: Config = "[options]\nIgnorePkg = vim-youcompleteme*\n[core-testing]\nServer = https://a.example/core-testing".parse()?;
+    /// let shell = config.shell()?.to_string();
+    ///
+    /// assert!(shell.contains("PACMAN_IGNOREPKG=('vim-youcompleteme*')\n"));
+    /// assert!(shell.contains("PACMAN_REPOS=('core-testing')\n"));
+    /// assert!(shell.contains("PACMAN_REPO_CORE_TESTING_SERVER=('https://a.example/core-testing')\n"));
+    ///
+    /// let config: Config = "[core-testing]\n[core_testing]\n".parse()?;
+    /// assert!(config.shell().is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn shell(&self) -> Result<Shell<'_>, Error> {
+        for (i, repo) in self.repos.iter().enumerate() {
+            let name = var("", &repo.name);
+            if let Some(other) = self.repos[..i].iter().find(|r| var("", &r.name) == name) {
+                let kind = ErrorKind::ShellName(other.name.clone(), repo.name.clone(), name);
+                return Err(kind.into());
+            }
+        }
+
+        Ok(Shell { config: self })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shell() {
+        let config: Config = "
+[options]
+DBPath = /var/lib/pacman/
+XferCommand = /usr/bin/curl -o '%o' %u
+Color
+HoldPkg = pacman
+HoldPkg = glibc
+[core]
+Server = https://a.example/core
+Usage = All
+"
+        .parse()
+        .unwrap();
+
+        let shell = config.shell().unwrap().to_string();
+        let lines = shell.lines().collect::<Vec<_>>();
+
+        assert!(lines.contains(&"PACMAN_DBPATH='/var/lib/pacman/'"));
+        assert!(lines.contains(&r"PACMAN_XFERCOMMAND='/usr/bin/curl -o '\''%o'\'' %u'"));
+        assert!(lines.contains(&"PACMAN_DOWNLOADUSER=''"));
+        assert!(lines.contains(&"PACMAN_COLOR=1"));
+        assert!(lines.contains(&"PACMAN_CHECKSPACE=0"));
+        assert!(lines.contains(&"PACMAN_HOLDPKG=('pacman' 'glibc')"));
+        assert!(lines.contains(&"PACMAN_IGNOREPKG=()"));
+        assert!(lines.contains(&"PACMAN_REPOS=('core')"));
+        assert!(lines.contains(&"PACMAN_REPO_CORE_SERVER=('https://a.example/core')"));
+        assert!(lines.contains(&"PACMAN_REPO_CORE_SIGLEVEL=()"));
+        assert!(lines.contains(&"PACMAN_REPO_CORE_USAGE=('All')"));
+    }
+
+    #[test]
+    fn collision() {
+        let config: Config = "[core-testing]\n[extra]\n[Core_Testing]\n".parse().unwrap();
+        let err = config.shell().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Repositories 'core-testing' and 'Core_Testing' are both exported as 'CORE_TESTING'"
+        );
+
+        let config: Config = "[core-testing]\n[testing]\n".parse().unwrap();
+        assert!(config.shell().is_ok());
+    }
+}
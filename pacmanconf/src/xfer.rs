@@ -0,0 +1,457 @@
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, ErrorKind};
+use crate::Config;
+
+/// How long a download may go without receiving data before it is stopped.
+///
+/// This matches pacman's own downloader, which gives up on a transfer that stays below
+/// one byte per second for ten seconds unless DisableDownloadTimeout is set.
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Splits a command into words the way pacman does, without using a shell.
+///
+/// Words are separated by whitespace, single and double quotes group words and a
+/// backslash escapes the next character anywhere. None if a quote is not closed.
+fn wordsplit(command: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut chars = command.chars().peekable();
+
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            return Some(words);
+        }
+
+        let mut word = String::new();
+        let mut quote = None;
+        while let Some(c) = chars.next() {
+            match (c, quote) {
+                ('\\', _) => word.push(chars.next().unwrap_or('\\')),
+                (c, Some(q)) if c == q => quote = None,
+                (c, Some(_)) => word.push(c),
+                ('\'', None) | ('"', None) => quote = Some(c),
+                (c, None) if c.is_whitespace() => break,
+                (c, None) => word.push(c),
+            }
+        }
+        if quote.is_some() {
+            return None;
+        }
+        words.push(word);
+    }
+}
+
+/// Replaces `%u` in a word with the URL and `%o` with the output path, in one pass so
+/// neither is substituted into the other.
+fn substitute(word: &str, url: &str, output: &Path) -> OsString {
+    let mut arg = OsString::new();
+    for (i, part) in word.split("%o").enumerate() {
+        if i > 0 {
+            arg.push(output);
+        }
+        arg.push(part.replace("%u", url));
+    }
+    arg
+}
+
+/// A parsed XferCommand, the external program pacman uses to download files.
+///
+/// The command is split into words without a shell. `%u` is replaced with the URL and
+/// `%o` with the file to download to, and if no word holds `%u` the URL is added as the
+/// last argument.
+///
+/// ```
+/// use pacmanconf::XferCommand;
+/// use std::path::Path;
+///
+/// let xfer: XferCommand = "/usr/bin/curl -L -C - -f -o %o %u".parse().unwrap();
+/// let args = xfer.args("https://a.example/core.db", Path::new("/tmp/core.db.part"));
+/// assert_eq!(args, ["/usr/bin/curl", "-L", "-C", "-", "-f", "-o", "/tmp/core.db.part", "https://a.example/core.db"]);
+///
+/// let xfer: XferCommand = "wget --passive-ftp -c".parse().unwrap();
+/// let args = xfer.args("https://a.example/core.db", Path::new("/tmp/core.db.part"));
+/// assert_eq!(args, ["wget", "--passive-ftp", "-c", "https://a.example/core.db"]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct XferCommand {
+    words: Vec<String>,
+    stall_timeout: Option<Duration>,
+    download_user: Option<String>,
+}
+
+impl FromStr for XferCommand {
+    type Err = Error;
+
+    /// Parses a command with the [`STALL_TIMEOUT`].
+    fn from_str(command: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || ErrorKind::InvalidValue("options".into(), "XferCommand".into(), command.into());
+
+        let words = wordsplit(command).ok_or_else(invalid)?;
+        if words.is_empty() {
+            return Err(invalid().into());
+        }
+
+        Ok(XferCommand {
+            words,
+            stall_timeout: Some(STALL_TIMEOUT),
+            download_user: None,
+        })
+    }
+}
+
+impl XferCommand {
+    /// The XferCommand of a config, or None if it is not set.
+    ///
+    /// The stall timeout is turned off if the config sets DisableDownloadTimeout, and the
+    /// command is run as the config's DownloadUser.
+    pub fn from_config(config: &Config) -> Result<Option<XferCommand>, Error> {
+        if config.xfer_command.is_empty() {
+            return Ok(None);
+        }
+
+        let mut xfer = config.xfer_command.parse::<XferCommand>()?;
+        if config.disable_download_timeout {
+            xfer.stall_timeout(None);
+        }
+        xfer.download_user(config.download_user.clone());
+        Ok(Some(xfer))
+    }
+
+    /// Sets how long [`run`](XferCommand::run) lets the command go without writing any
+    /// data before stopping it. None waits forever.
+    pub fn stall_timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.stall_timeout = timeout;
+        self
+    }
+
+    /// Sets the user [`run`](XferCommand::run) runs the command as, as pacman does with
+    /// DownloadUser.
+    ///
+    /// Privileges can only be dropped by root on unix, elsewhere the user is ignored.
+    pub fn download_user(&mut self, user: Option<String>) -> &mut Self {
+        self.download_user = user;
+        self
+    }
+
+    /// The words of the command before `%u` and `%o` are replaced.
+    pub fn words(&self) -> &[String] {
+        &self.words
+    }
+
+    /// Whether the command is told which file to download to with `%o`.
+    ///
+    /// Otherwise the program chooses the name, usually the last component of the URL, in
+    /// the directory it is run in.
+    pub fn uses_output(&self) -> bool {
+        self.words.iter().any(|w| w.contains("%o"))
+    }
+
+    /// The program and its arguments for downloading `url` to `output`.
+    pub fn args(&self, url: &str, output: &Path) -> Vec<OsString> {
+        let mut args = self
+            .words
+            .iter()
+            .map(|word| substitute(word, url, output))
+            .collect::<Vec<_>>();
+        if !self.words.iter().any(|w| w.contains("%u")) {
+            args.push(url.into());
+        }
+        args
+    }
+
+    /// A command for downloading `url` to `output`, run in the directory of `output`.
+    pub fn command(&self, url: &str, output: &Path) -> Command {
+        let args = self.args(url, output);
+        let mut cmd = Command::new(&args[0]);
+        cmd.args(&args[1..]);
+        if let Some(dir) = output.parent().filter(|d| !d.as_os_str().is_empty()) {
+            cmd.current_dir(dir);
+        }
+        cmd
+    }
+
+    /// Downloads `url` to the file `dest` as pacman does.
+    ///
+    /// If the command uses `%o` it downloads to `dest` with `.part` appended, which is
+    /// renamed to `dest` once the command succeeds, so a partial download can be resumed
+    /// by programs that support it. Otherwise the program is expected to name the file
+    /// after the last component of the URL in the directory of `dest`, which is renamed
+    /// to `dest`. The command's output is not captured.
+    ///
+    /// The command is stopped if the file it downloads to does not grow for the
+    /// [`stall_timeout`](XferCommand::stall_timeout), and it is an error for the command
+    /// to succeed without creating the file.
+    ///
+    /// When run as the [`download_user`](XferCommand::download_user) the `.part` file is
+    /// created first and given to the user so the command can write to it. Commands that
+    /// do not use `%o` must be able to write to the directory of `dest` themselves.
+    pub fn run(&self, url: &str, dest: &Path) -> Result<(), Error> {
+        let failed = |reason: String| -> Error {
+            ErrorKind::Download(format!("{}: {}", url, reason)).into()
+        };
+        let part = match self.uses_output() {
+            true => {
+                let mut part = dest.as_os_str().to_os_string();
+                part.push(".part");
+                PathBuf::from(part)
+            }
+            false => match url.rsplit('/').next().filter(|name| !name.is_empty()) {
+                Some(name) => dest.with_file_name(name),
+                None => return Err(failed("the URL does not end in a file name".into())),
+            },
+        };
+
+        let mut cmd = self.command(url, &part);
+        #[cfg(unix)]
+        self.drop_privileges(&mut cmd, &part)?;
+        let program = cmd.get_program().to_os_string();
+        let mut child = cmd
+            .stdin(Stdio::null())
+            .spawn()
+            .map_err(|e| failed(format!("could not run {}: {}", display(&program), e)))?;
+
+        let status = match self.stall_timeout {
+            None => child.wait()?,
+            Some(timeout) => {
+                let size = || fs::metadata(&part).map(|m| m.len()).ok();
+                let mut last = size();
+                let mut since = Instant::now();
+
+                loop {
+                    if let Some(status) = child.try_wait()? {
+                        break status;
+                    }
+                    let now = size();
+                    if now != last {
+                        last = now;
+                        since = Instant::now();
+                    } else if since.elapsed() >= timeout {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        return Err(failed(format!("no data received for {:?}", timeout)));
+                    }
+                    thread::sleep(Duration::from_millis(50));
+                }
+            }
+        };
+
+        if !status.success() {
+            return Err(failed(format!("{} failed ({})", display(&program), status)));
+        }
+        if !part.exists() {
+            let reason = format!("{} did not create {}", display(&program), part.display());
+            return Err(failed(reason));
+        }
+        if part != dest {
+            fs::rename(&part, dest)?;
+        }
+        Ok(())
+    }
+
+    /// Makes the command run as the download user when running as root.
+    #[cfg(unix)]
+    fn drop_privileges(&self, cmd: &mut Command, part: &Path) -> Result<(), Error> {
+        use std::os::unix::process::CommandExt;
+
+        let user = match &self.download_user {
+            Some(user) if unsafe { libc::geteuid() } == 0 => user,
+            _ => return Ok(()),
+        };
+
+        let (uid, gid) = lookup_user(user)?;
+        if self.uses_output() {
+            fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(part)?;
+            std::os::unix::fs::chown(part, Some(uid), Some(gid))?;
+        }
+        cmd.uid(uid).gid(gid);
+        Ok(())
+    }
+}
+
+/// Looks up the uid and gid of a user.
+#[cfg(unix)]
+pub(crate) fn lookup_user(name: &str) -> Result<(u32, u32), Error> {
+    use std::ffi::CString;
+    use std::{mem, ptr};
+
+    let unknown = || ErrorKind::InvalidValue("options".into(), "DownloadUser".into(), name.into());
+    let cname = CString::new(name).map_err(|_| unknown())?;
+    let mut buf = vec![0 as libc::c_char; 16384];
+    let mut pwd: libc::passwd = unsafe { mem::zeroed() };
+    let mut result = ptr::null_mut();
+
+    unsafe {
+        libc::getpwnam_r(
+            cname.as_ptr(),
+            &mut pwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if result.is_null() {
+        return Err(unknown().into());
+    }
+    Ok((pwd.pw_uid, pwd.pw_gid))
+}
+
+fn display(program: &OsStr) -> String {
+    program.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let words = |s: &str| s.parse::<XferCommand>().unwrap().words;
+
+        assert_eq!(words("  curl -o %o   %u "), ["curl", "-o", "%o", "%u"]);
+        assert_eq!(
+            words(r#"sh -c 'exec curl "$0" > "$1"' %u %o"#),
+            ["sh", "-c", r#"exec curl "$0" > "$1""#, "%u", "%o"]
+        );
+        assert_eq!(
+            words(r#"dl "a b"c\ d 'e\'f' \"#),
+            ["dl", "a bc d", "e'f", "\\"]
+        );
+        assert!("curl 'unterminated".parse::<XferCommand>().is_err());
+        assert!("   ".parse::<XferCommand>().is_err());
+    }
+
+    #[test]
+    fn args() {
+        let xfer: XferCommand = "fetch --output=%o --url %u".parse().unwrap();
+        assert!(xfer.uses_output());
+        assert_eq!(
+            xfer.args("https://a.example/x.pkg", Path::new("/tmp/x.pkg.part")),
+            [
+                "fetch",
+                "--output=/tmp/x.pkg.part",
+                "--url",
+                "https://a.example/x.pkg"
+            ]
+        );
+
+        // neither value is substituted into the other
+        assert_eq!(
+            xfer.args("https://a.example/%o%u", Path::new("/tmp/%u.part")),
+            [
+                "fetch",
+                "--output=/tmp/%u.part",
+                "--url",
+                "https://a.example/%o%u"
+            ]
+        );
+
+        let config = Config {
+            xfer_command: "wget -c".into(),
+            disable_download_timeout: true,
+            ..Config::default()
+        };
+        let xfer = XferCommand::from_config(&config).unwrap().unwrap();
+        assert!(!xfer.uses_output());
+        assert_eq!(xfer.stall_timeout, None);
+        assert_eq!(
+            xfer.args("https://a.example/x.pkg", Path::new("x.pkg")),
+            ["wget", "-c", "https://a.example/x.pkg"]
+        );
+        assert_eq!(XferCommand::from_config(&Config::default()).unwrap(), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run() {
+        let dir = std::env::temp_dir().join(format!("pacmanconf-xfer-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("src.pkg");
+        let dest = dir.join("dest.pkg");
+        fs::write(&src, "package").unwrap();
+        let url = src.to_str().unwrap();
+
+        let xfer: XferCommand = "cp %u %o".parse().unwrap();
+        xfer.run(url, &dest).unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "package");
+        assert!(!dir.join("dest.pkg.part").exists());
+
+        let xfer: XferCommand = "false".parse().unwrap();
+        let err = xfer.run(url, &dir.join("fail.pkg")).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::Download(_)));
+
+        let xfer: XferCommand = "true %u %o".parse().unwrap();
+        let err = xfer.run(url, &dir.join("empty.pkg")).unwrap_err();
+        assert!(err.to_string().contains("did not create"));
+
+        let mut xfer: XferCommand = "sh -c 'sleep 5' %u %o".parse().unwrap();
+        xfer.stall_timeout(Some(Duration::from_millis(200)));
+        let start = Instant::now();
+        let err = xfer.run(url, &dir.join("stall.pkg")).unwrap_err();
+        assert!(err.to_string().contains("no data received"));
+        assert!(start.elapsed() < Duration::from_secs(4));
+
+        // without %o the file is named after the URL in the directory of dest
+        let out = dir.join("out");
+        fs::create_dir_all(&out).unwrap();
+        let xfer: XferCommand = "cp %u .".parse().unwrap();
+        xfer.run(url, &out.join("copy.pkg")).unwrap();
+        assert_eq!(fs::read_to_string(out.join("copy.pkg")).unwrap(), "package");
+        assert!(!out.join("src.pkg").exists());
+
+        let mut xfer: XferCommand =
+            r#"sh -c 'for i in 1 2 3 4 5 6; do printf a >> "${0##*/}"; sleep 0.1; done' %u"#
+                .parse()
+                .unwrap();
+        xfer.stall_timeout(Some(Duration::from_millis(300)));
+        xfer.run(url, &out.join("slow.pkg")).unwrap();
+        assert_eq!(fs::read_to_string(out.join("slow.pkg")).unwrap(), "aaaaaa");
+
+        let xfer: XferCommand = "true".parse().unwrap();
+        let err = xfer.run(url, &out.join("never.pkg")).unwrap_err();
+        assert!(err.to_string().contains("did not create"));
+        assert!(xfer.run("https://a.example/", &out.join("x.pkg")).is_err());
+
+        let xfer: XferCommand = "/nonexistent/fetch %u".parse().unwrap();
+        assert!(xfer.run(url, &dir.join("missing.pkg")).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn download_user() {
+        assert_eq!(lookup_user("root").unwrap(), (0, 0));
+        assert!(lookup_user("no such user").is_err());
+
+        if unsafe { libc::geteuid() } != 0 || lookup_user("nobody").is_err() {
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("pacmanconf-xfer-user-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dest = dir.join("uid");
+
+        let mut xfer: XferCommand = r#"sh -c 'id -u > "$1"' %u %o"#.parse().unwrap();
+        xfer.download_user(Some("nobody".into()));
+        xfer.run("file:///uid", &dest).unwrap();
+        let uid = lookup_user("nobody").unwrap().0;
+        assert_eq!(fs::read_to_string(&dest).unwrap().trim(), uid.to_string());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}